@@ -0,0 +1,491 @@
+use std::collections::HashSet;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use futures::stream::{self, Stream, StreamExt};
+use reqwest::header::LINK;
+use reqwest::{Client, RequestBuilder, Url};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use tokio::time::timeout;
+
+use crate::limit::host_key;
+use crate::retry::FetchError;
+
+// Credentials sent with every API request
+#[derive(Clone, Debug, Default)]
+pub enum Auth {
+    #[default]
+    None,
+    Bearer(String),
+    Basic {
+        user: String,
+        password: Option<String>,
+    },
+}
+
+impl Auth {
+    fn apply(&self, req: RequestBuilder) -> RequestBuilder {
+        match self {
+            Auth::None => req,
+            Auth::Bearer(token) => req.bearer_auth(token),
+            Auth::Basic { user, password } => req.basic_auth(user, password.as_ref()),
+        }
+    }
+}
+
+// `bearer:TOKEN` or `basic:USER[:PASSWORD]`, as given to `--auth`
+impl FromStr for Auth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.split_once(':') {
+            Some(("bearer", token)) if !token.is_empty() => Ok(Auth::Bearer(token.to_string())),
+            Some(("basic", creds)) if !creds.is_empty() => {
+                let (user, password) = match creds.split_once(':') {
+                    Some((u, p)) => (u, Some(p.to_string())),
+                    None => (creds, None),
+                };
+                Ok(Auth::Basic {
+                    user: user.to_string(),
+                    password,
+                })
+            }
+            _ => Err(format!(
+                "expected bearer:TOKEN or basic:USER[:PASSWORD], got `{s}`"
+            )),
+        }
+    }
+}
+
+// GET `url` and deserialize the JSON body, with the same status check,
+// timeout and error context as `fetch_task`
+pub async fn fetch_json<T: DeserializeOwned>(client: &Client, url: &str, auth: &Auth) -> Result<T> {
+    let (_, body) = get_json(client, url, auth).await?;
+    serde_json::from_value(body).with_context(|| format!("unexpected JSON shape from {url}"))
+}
+
+// The body as loose JSON, plus the `Link` header for the paginator
+async fn get_json(client: &Client, url: &str, auth: &Auth) -> Result<(Option<String>, Value)> {
+    let fut = async {
+        let resp = auth
+            .apply(client.get(url))
+            .send()
+            .await
+            .with_context(|| format!("request failed: GET {url}"))?;
+        let status = resp.status();
+        if !status.is_success() {
+            anyhow::bail!("{url} -> HTTP {status}");
+        }
+        let link = resp
+            .headers()
+            .get(LINK)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let bytes = resp
+            .bytes()
+            .await
+            .with_context(|| format!("read body failed: {url}"))?;
+        let body =
+            serde_json::from_slice(&bytes).with_context(|| format!("bad JSON from {url}"))?;
+        Ok((link, body))
+    };
+    timeout(Duration::from_secs(10), fut)
+        .await
+        .map_err(|_| anyhow::Error::new(FetchError::Timeout))
+        .flatten()
+}
+
+// How to find the page after this one
+#[derive(Clone, Debug)]
+pub enum Paging {
+    // Follow `Link: <...>; rel="next"` until a page has none
+    Link,
+    // Take the next cursor from `field` (a JSON pointer such as
+    // `/meta/next`) and send it as `?param=`; stop when it's missing or empty
+    Cursor { field: String, param: String },
+    // Send `?param=start`, `start + 1`, ... until a page has no items
+    Page { param: String, start: u64 },
+}
+
+#[derive(Clone, Debug)]
+pub struct PageOptions {
+    pub paging: Paging,
+    // JSON pointer to the item array in each page; `None` when the page
+    // itself is the array
+    pub items: Option<String>,
+    pub auth: Auth,
+    // Stop after this many pages even if there are more
+    pub max_pages: Option<usize>,
+}
+
+impl Default for PageOptions {
+    fn default() -> Self {
+        Self {
+            paging: Paging::Link,
+            items: None,
+            auth: Auth::None,
+            max_pages: None,
+        }
+    }
+}
+
+struct Cursor {
+    next: Option<Url>,
+    page: u64,
+    pages: usize,
+    // Origin (scheme, host, port) of the first URL: credentials are only
+    // ever sent there
+    origin: String,
+    // Every URL fetched, and a hash of every page body, to catch a server
+    // that keeps handing back the same page
+    seen_urls: HashSet<Url>,
+    seen_pages: HashSet<u64>,
+}
+
+// Every item of every page, fetched one page at a time as the stream is
+// polled. The first error ends the stream; so does a next page on another
+// origin (it would get our credentials) or one we've already seen.
+pub fn paginate<T>(client: Client, url: &str, opts: PageOptions) -> impl Stream<Item = Result<T>>
+where
+    T: DeserializeOwned,
+{
+    let first = Url::parse(url).with_context(|| format!("bad URL: {url}"));
+    let start = match &opts.paging {
+        Paging::Page { param, start } => first.map(|u| with_param(&u, param, &start.to_string())),
+        _ => first,
+    };
+    let (next, first_err) = match start {
+        Ok(u) => (Some(u), None),
+        Err(e) => (None, Some(e)),
+    };
+    let page = match opts.paging {
+        Paging::Page { start, .. } => start,
+        _ => 0,
+    };
+    let cursor = Cursor {
+        origin: next.as_ref().map(host_key).unwrap_or_default(),
+        next,
+        page,
+        pages: 0,
+        seen_urls: HashSet::new(),
+        seen_pages: HashSet::new(),
+    };
+
+    let pages = stream::unfold(
+        (cursor, client, opts),
+        |(mut cursor, client, opts)| async move {
+            let url = cursor.next.take()?;
+            if opts.max_pages.is_some_and(|max| cursor.pages >= max) {
+                return None;
+            }
+            if host_key(&url) != cursor.origin {
+                let err = anyhow::anyhow!("next page {url} is on another origin, not following it");
+                return Some((Err(err), (cursor, client, opts)));
+            }
+            if !cursor.seen_urls.insert(url.clone()) {
+                let err = anyhow::anyhow!("{url} was already fetched, stopping");
+                return Some((Err(err), (cursor, client, opts)));
+            }
+            cursor.pages += 1;
+            let page = next_page::<T>(&client, url, &opts, &mut cursor).await;
+            Some((page, (cursor, client, opts)))
+        },
+    );
+    stream::iter(first_err.map(Err))
+        .chain(pages)
+        .flat_map(|page: Result<Vec<T>>| {
+            let items: Vec<Result<T>> = match page {
+                Ok(items) => items.into_iter().map(Ok).collect(),
+                Err(e) => vec![Err(e)],
+            };
+            stream::iter(items)
+        })
+}
+
+// Fetch one page, leaving the URL of the following one (if any) in `cursor`
+async fn next_page<T: DeserializeOwned>(
+    client: &Client,
+    url: Url,
+    opts: &PageOptions,
+    cursor: &mut Cursor,
+) -> Result<Vec<T>> {
+    let (link, mut body) = get_json(client, url.as_str(), &opts.auth).await?;
+    let mut hash = DefaultHasher::new();
+    body.to_string().hash(&mut hash);
+    if !cursor.seen_pages.insert(hash.finish()) {
+        anyhow::bail!("{url} returned the same page as before, stopping");
+    }
+    let items = match &opts.items {
+        Some(pointer) => body
+            .pointer_mut(pointer)
+            .map(Value::take)
+            .with_context(|| format!("no `{pointer}` in the page from {url}"))?,
+        None => body.take(),
+    };
+    let items: Vec<T> = serde_json::from_value(items)
+        .with_context(|| format!("unexpected JSON shape from {url}"))?;
+
+    cursor.next = match &opts.paging {
+        Paging::Link => link.as_deref().and_then(|l| next_link(l, &url)),
+        Paging::Cursor { field, param } => match body.pointer(field) {
+            Some(Value::String(s)) if !s.is_empty() => Some(with_param(&url, param, s)),
+            Some(Value::Number(n)) => Some(with_param(&url, param, &n.to_string())),
+            _ => None,
+        },
+        Paging::Page { param, .. } if !items.is_empty() => {
+            cursor.page += 1;
+            Some(with_param(&url, param, &cursor.page.to_string()))
+        }
+        Paging::Page { .. } => None,
+    };
+    Ok(items)
+}
+
+// The `rel="next"` target of a Link header, resolved against `base`
+fn next_link(header: &str, base: &Url) -> Option<Url> {
+    header.split(',').find_map(|part| {
+        let mut fields = part.split(';');
+        let target = fields.next()?.trim().strip_prefix('<')?.strip_suffix('>')?;
+        let is_next = fields.any(|f| {
+            f.trim()
+                .strip_prefix("rel=")
+                .is_some_and(|rel| rel.trim_matches('"').split(' ').any(|r| r == "next"))
+        });
+        is_next.then(|| base.join(target).ok()).flatten()
+    })
+}
+
+// `url` with `key` set to `value`, replacing any earlier value
+fn with_param(url: &Url, key: &str, value: &str) -> Url {
+    let kept: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(k, _)| k != key)
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect();
+    let mut url = url.clone();
+    url.query_pairs_mut()
+        .clear()
+        .extend_pairs(kept)
+        .append_pair(key, value);
+    url
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::test_server::{Route, TestServer};
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Item {
+        id: u32,
+    }
+
+    fn json(body: &str) -> Route {
+        Route {
+            headers: vec![("Content-Type".into(), "application/json".into())],
+            ..Route::ok(body)
+        }
+    }
+
+    async fn collect(srv: &TestServer, path: &str, opts: PageOptions) -> Vec<u32> {
+        paginate::<Item>(Client::new(), &srv.url(path), opts)
+            .map(|item| item.unwrap().id)
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn follows_links_cursors_and_page_numbers() {
+        let srv = TestServer::start(HashMap::new()).await;
+        srv.set_route(
+            "/links",
+            Route {
+                headers: vec![(
+                    "Link".into(),
+                    "</links?p=2>; rel=\"next\", </links>; rel=\"first\"".into(),
+                )],
+                ..json(r#"[{"id":1},{"id":2}]"#)
+            },
+        );
+        srv.set_route("/links?p=2", json(r#"[{"id":3}]"#));
+        assert_eq!(
+            collect(&srv, "/links", PageOptions::default()).await,
+            [1, 2, 3]
+        );
+
+        srv.set_route("/c", json(r#"{"data":[{"id":1}],"meta":{"next":"abc"}}"#));
+        srv.set_route(
+            "/c?after=abc",
+            json(r#"{"data":[{"id":2}],"meta":{"next":null}}"#),
+        );
+        let opts = PageOptions {
+            paging: Paging::Cursor {
+                field: "/meta/next".into(),
+                param: "after".into(),
+            },
+            items: Some("/data".into()),
+            ..PageOptions::default()
+        };
+        assert_eq!(collect(&srv, "/c", opts).await, [1, 2]);
+
+        srv.set_route("/n?page=1", json(r#"[{"id":1}]"#));
+        srv.set_route("/n?page=2", json(r#"[{"id":2}]"#));
+        srv.set_route("/n?page=3", json("[]"));
+        let opts = PageOptions {
+            paging: Paging::Page {
+                param: "page".into(),
+                start: 1,
+            },
+            ..PageOptions::default()
+        };
+        assert_eq!(collect(&srv, "/n", opts.clone()).await, [1, 2]);
+        let capped = PageOptions {
+            max_pages: Some(1),
+            ..opts
+        };
+        assert_eq!(collect(&srv, "/n", capped).await, [1]);
+    }
+
+    #[tokio::test]
+    async fn sends_auth_and_reports_bad_pages() {
+        let srv = TestServer::start(HashMap::new()).await;
+        srv.set_route("/one", json(r#"{"id":7}"#));
+        let auth: Auth = "bearer:s3cret".parse().unwrap();
+        let item: Item = fetch_json(&Client::new(), &srv.url("/one"), &auth)
+            .await
+            .unwrap();
+        assert_eq!(item, Item { id: 7 });
+        assert!(srv.requests()[0].contains("authorization=Bearer s3cret"));
+
+        let basic: Auth = "basic:me:pw".parse().unwrap();
+        fetch_json::<Item>(&Client::new(), &srv.url("/one"), &basic)
+            .await
+            .unwrap();
+        // base64("me:pw")
+        assert!(srv.requests()[1].contains("authorization=Basic bWU6cHc="));
+        assert!("token".parse::<Auth>().is_err());
+
+        srv.set_route("/bad", json(r#"{"id":"x"}"#));
+        let err = fetch_json::<Item>(&Client::new(), &srv.url("/bad"), &Auth::None)
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string().starts_with("unexpected JSON shape"),
+            "{err}"
+        );
+
+        // A page that fails ends the stream after the items before it
+        srv.set_route(
+            "/p",
+            Route {
+                headers: vec![("Link".into(), "</gone>; rel=next".into())],
+                ..json(r#"[{"id":1}]"#)
+            },
+        );
+        let got: Vec<Result<Item>> =
+            paginate(Client::new(), &srv.url("/p"), PageOptions::default())
+                .collect()
+                .await;
+        assert_eq!(got.len(), 2);
+        assert!(
+            got[1]
+                .as_ref()
+                .unwrap_err()
+                .to_string()
+                .ends_with("HTTP 404 Not Found")
+        );
+    }
+
+    #[tokio::test]
+    async fn never_follows_links_to_another_origin() {
+        let srv = TestServer::start(HashMap::new()).await;
+        let other = TestServer::start(HashMap::from([("/steal".to_string(), json("[]"))])).await;
+        srv.set_route(
+            "/p",
+            Route {
+                headers: vec![(
+                    "Link".into(),
+                    format!("<{}>; rel=next", other.url("/steal")),
+                )],
+                ..json(r#"[{"id":1}]"#)
+            },
+        );
+        let opts = PageOptions {
+            auth: "bearer:s3cret".parse().unwrap(),
+            ..PageOptions::default()
+        };
+        let got: Vec<Result<Item>> = paginate(Client::new(), &srv.url("/p"), opts)
+            .collect()
+            .await;
+        assert_eq!(got.len(), 2);
+        assert_eq!(got[0].as_ref().unwrap(), &Item { id: 1 });
+        let err = got[1].as_ref().unwrap_err().to_string();
+        assert!(
+            err.ends_with("is on another origin, not following it"),
+            "{err}"
+        );
+        assert!(other.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn stops_when_a_page_comes_back_again() {
+        let srv = TestServer::start(HashMap::new()).await;
+        let last_err =
+            |got: Vec<Result<Item>>| got.last().unwrap().as_ref().unwrap_err().to_string();
+
+        // A Link pointing back at the page itself
+        srv.set_route(
+            "/loop",
+            Route {
+                headers: vec![("Link".into(), "</loop>; rel=next".into())],
+                ..json(r#"[{"id":1}]"#)
+            },
+        );
+        let got: Vec<Result<Item>> =
+            paginate(Client::new(), &srv.url("/loop"), PageOptions::default())
+                .collect()
+                .await;
+        assert_eq!(got.len(), 2);
+        assert!(last_err(got).ends_with("was already fetched, stopping"));
+
+        // A cursor that never moves on
+        srv.set_route("/c", json(r#"{"data":[{"id":1}],"next":"abc"}"#));
+        srv.set_route("/c?after=abc", json(r#"{"data":[{"id":2}],"next":"abc"}"#));
+        let opts = PageOptions {
+            paging: Paging::Cursor {
+                field: "/next".into(),
+                param: "after".into(),
+            },
+            items: Some("/data".into()),
+            ..PageOptions::default()
+        };
+        let got: Vec<Result<Item>> = paginate(Client::new(), &srv.url("/c"), opts)
+            .collect()
+            .await;
+        assert_eq!(got.len(), 3);
+        assert!(last_err(got).ends_with("was already fetched, stopping"));
+
+        // A page parameter the server ignores
+        srv.set_route("/n?page=1", json(r#"[{"id":1}]"#));
+        srv.set_route("/n?page=2", json(r#"[{"id":1}]"#));
+        let opts = PageOptions {
+            paging: Paging::Page {
+                param: "page".into(),
+                start: 1,
+            },
+            ..PageOptions::default()
+        };
+        let got: Vec<Result<Item>> = paginate(Client::new(), &srv.url("/n"), opts)
+            .collect()
+            .await;
+        assert_eq!(got.len(), 2);
+        assert!(last_err(got).ends_with("returned the same page as before, stopping"));
+    }
+}
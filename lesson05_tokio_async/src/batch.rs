@@ -0,0 +1,307 @@
+use std::fmt;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use futures::stream::{self, Stream, StreamExt};
+use reqwest::Client;
+
+use crate::cache::{Cache, Source};
+use crate::fetch::fetch_task;
+use crate::limit::HostLimiter;
+use crate::retry::{RetryPolicy, fetch_with_retry};
+
+// In which order finished fetches come out of the stream
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Order {
+    // As soon as each one finishes
+    Completion,
+    // Same order as the input URLs (a slow early URL holds back later ones)
+    Input,
+}
+
+// What one fetch in a batch came back with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fetched {
+    pub len: usize,
+    // Served from the cache: offline, or the server answered 304
+    pub from_cache: bool,
+}
+
+// Totals over everything a `FetchMany` stream has yielded so far
+#[derive(Clone, Debug, Default)]
+pub struct Summary {
+    pub ok: usize,
+    pub err: usize,
+    pub total_bytes: usize,
+    pub cached: usize,
+    latencies: Vec<Duration>,
+}
+
+impl Summary {
+    fn record(&mut self, res: &Result<Fetched>, latency: Duration) {
+        match res {
+            Ok(f) => {
+                self.ok += 1;
+                self.total_bytes += f.len;
+                self.cached += usize::from(f.from_cache);
+            }
+            Err(_) => self.err += 1,
+        }
+        self.latencies.push(latency);
+    }
+
+    // Nearest-rank percentile over all fetches (failed ones included)
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        if self.latencies.is_empty() {
+            return None;
+        }
+        let mut sorted = self.latencies.clone();
+        sorted.sort();
+        let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+        Some(sorted[rank.clamp(1, sorted.len()) - 1])
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ms =
+            |d: Option<Duration>| d.map_or("-".to_string(), |d| format!("{}ms", d.as_millis()));
+        write!(f, "ok: {}, err: {}", self.ok, self.err)?;
+        if self.cached > 0 {
+            write!(f, ", from cache: {}", self.cached)?;
+        }
+        write!(
+            f,
+            ", total: {} bytes, p50: {}, p95: {}",
+            self.total_bytes,
+            ms(self.percentile(50.0)),
+            ms(self.percentile(95.0)),
+        )
+    }
+}
+
+pub struct BatchOptions {
+    // Max requests in flight
+    pub concurrency: usize,
+    pub order: Order,
+    // Retry transient failures; `None` = one `fetch_task` attempt per URL
+    pub retry: Option<RetryPolicy>,
+    // Per-host rate / concurrency limits, applied before every request,
+    // each retry included
+    pub limiter: Option<HostLimiter>,
+    // Go through this on-disk cache (conditional requests, offline mode);
+    // when set, retries are not used
+    pub cache: Option<Arc<Cache>>,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 8,
+            order: Order::Completion,
+            retry: None,
+            limiter: None,
+            cache: None,
+        }
+    }
+}
+
+type Timed = (String, Result<Fetched>, Duration);
+
+// Stream of `(url, result)` that keeps a running `Summary` on the side
+pub struct FetchMany {
+    inner: Pin<Box<dyn Stream<Item = Timed> + Send>>,
+    summary: Summary,
+}
+
+impl FetchMany {
+    pub fn summary(&self) -> &Summary {
+        &self.summary
+    }
+}
+
+impl Stream for FetchMany {
+    type Item = (String, Result<Fetched>);
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        match self.inner.as_mut().poll_next(cx) {
+            Poll::Ready(Some((url, res, latency))) => {
+                self.summary.record(&res, latency);
+                Poll::Ready(Some((url, res)))
+            }
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+// Fetch every URL with at most `concurrency` requests in flight. Each fetch
+// goes through `fetch_task` (same timeout and error context), through
+// `fetch_with_retry` when a retry policy is set, or through the cache.
+pub fn fetch_many<I>(client: Client, urls: I, opts: &BatchOptions) -> FetchMany
+where
+    I: IntoIterator,
+    I::Item: Into<String>,
+    I::IntoIter: Send + 'static,
+{
+    let retry = opts.retry.clone();
+    let limiter = opts.limiter.clone();
+    let cache = opts.cache.clone();
+    let timed = stream::iter(urls).map(move |url| {
+        let client = client.clone();
+        let retry = retry.clone();
+        let limiter = limiter.clone();
+        let cache = cache.clone();
+        let url: String = url.into();
+        async move {
+            // Retries take the limiter per attempt, inside `fetch_with_retry`.
+            // Otherwise it's taken here, and waiting on it doesn't count as
+            // latency.
+            let per_attempt = cache.is_none() && retry.is_some();
+            let _permit = match &limiter {
+                Some(l) if !per_attempt => Some(l.acquire(&url).await),
+                _ => None,
+            };
+            let start = Instant::now();
+            let fresh = |len| Fetched {
+                len,
+                from_cache: false,
+            };
+            let res = match (&cache, retry) {
+                (Some(cache), _) => cache
+                    .fetch(&client, &url)
+                    .await
+                    .map(|(len, source)| Fetched {
+                        len: len as usize,
+                        from_cache: source != Source::Network,
+                    }),
+                (None, None) => fetch_task(&client, &url).await.1.map(fresh),
+                (None, Some(policy)) => {
+                    // Keep the typed error reachable via `downcast_ref`
+                    fetch_with_retry(&client, &url, &policy, limiter.as_ref())
+                        .await
+                        .map(fresh)
+                        .map_err(anyhow::Error::new)
+                }
+            };
+            (url, res, start.elapsed())
+        }
+    });
+
+    let concurrency = opts.concurrency.max(1);
+    let inner: Pin<Box<dyn Stream<Item = Timed> + Send>> = match opts.order {
+        Order::Completion => Box::pin(timed.buffer_unordered(concurrency)),
+        Order::Input => Box::pin(timed.buffered(concurrency)),
+    };
+    FetchMany {
+        inner,
+        summary: Summary::default(),
+    }
+}
+
+// One URL per line; blank lines and `#` comments are skipped
+pub fn parse_url_list(text: &str) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::test_server::{Route, TestServer};
+
+    fn stalled(ms: u64, body: &str) -> Route {
+        Route {
+            stall: Some(Duration::from_millis(ms)),
+            ..Route::ok(body)
+        }
+    }
+
+    #[tokio::test]
+    async fn never_exceeds_the_concurrency_cap() {
+        let srv = TestServer::start(HashMap::from([("/".to_string(), stalled(100, "abc"))])).await;
+        let opts = BatchOptions {
+            concurrency: 3,
+            ..BatchOptions::default()
+        };
+        let urls: Vec<String> = (0..10).map(|_| srv.url("/")).collect();
+        let mut batch = fetch_many(Client::new(), urls, &opts);
+        while batch.next().await.is_some() {}
+
+        assert_eq!(srv.requests().len(), 10);
+        assert_eq!(srv.peak_concurrency(), 3);
+        let summary = batch.summary();
+        assert_eq!((summary.ok, summary.err, summary.total_bytes), (10, 0, 30));
+    }
+
+    #[tokio::test]
+    async fn yields_in_completion_or_input_order() {
+        let srv = TestServer::start(HashMap::from([
+            ("/slow".to_string(), stalled(300, "slow")),
+            ("/fast".to_string(), stalled(100, "fast")),
+            (
+                "/gone".to_string(),
+                Route {
+                    status: 404,
+                    ..Route::ok("")
+                },
+            ),
+        ]))
+        .await;
+        let urls = vec![srv.url("/slow"), srv.url("/fast"), srv.url("/gone")];
+
+        for (order, want) in [
+            (Order::Input, ["/slow", "/fast", "/gone"]),
+            (Order::Completion, ["/gone", "/fast", "/slow"]),
+        ] {
+            let opts = BatchOptions {
+                order,
+                ..BatchOptions::default()
+            };
+            let batch = fetch_many(Client::new(), urls.clone(), &opts);
+            let got: Vec<(String, bool)> =
+                batch.map(|(url, res)| (url, res.is_ok())).collect().await;
+            let paths: Vec<&str> = got
+                .iter()
+                .map(|(url, _)| url.trim_start_matches(&srv.url("")))
+                .collect();
+            assert_eq!(paths, want, "{order:?}");
+            assert!(got.iter().all(|(url, ok)| *ok != url.ends_with("/gone")));
+        }
+    }
+
+    #[test]
+    fn percentiles_use_nearest_rank() {
+        let mut summary = Summary::default();
+        assert_eq!(summary.percentile(50.0), None);
+        // 1ms..=20ms, recorded out of order; the failures count too
+        for ms in (1..=20).rev() {
+            let res = if ms % 5 == 0 {
+                Err(anyhow::anyhow!("boom"))
+            } else {
+                Ok(Fetched {
+                    len: 10,
+                    from_cache: false,
+                })
+            };
+            summary.record(&res, Duration::from_millis(ms));
+        }
+        let ms = |p| summary.percentile(p).unwrap().as_millis();
+        assert_eq!(ms(50.0), 10);
+        assert_eq!(ms(95.0), 19);
+        assert_eq!(ms(100.0), 20);
+        assert_eq!(ms(0.0), 1);
+        assert_eq!(
+            summary.to_string(),
+            "ok: 16, err: 4, total: 160 bytes, p50: 10ms, p95: 19ms"
+        );
+    }
+}
@@ -0,0 +1,365 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use futures::StreamExt;
+use reqwest::Client;
+use serde::Serialize;
+use tokio::sync::{Semaphore, mpsc};
+use tokio::task::JoinSet;
+use tokio::time::{Instant, MissedTickBehavior, interval_at, timeout};
+
+use crate::histogram::Histogram;
+use crate::limit;
+use crate::retry::FetchError;
+
+// Longest --duration/--warmup accepted; keeps `now + warmup + duration`
+// well inside what an Instant can hold
+const MAX_SECS: u64 = 7 * 24 * 60 * 60;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Model {
+    // Fixed number of connections, each sending its next request as soon as
+    // the previous one is done
+    Closed { concurrency: usize },
+    // Requests start on a fixed schedule whether or not earlier ones have
+    // finished; past `max_in_flight` a request is dropped and counted
+    Open { rate: f64, max_in_flight: usize },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Limit {
+    Duration(Duration),
+    Requests(u64),
+}
+
+pub struct BenchOptions {
+    pub model: Model,
+    pub limit: Limit,
+    // Requests started during warmup are sent but not measured
+    pub warmup: Duration,
+    pub timeout: Duration,
+}
+
+// `--warmup`: seconds, finite and between 0 and a week
+pub fn parse_secs(s: &str) -> Result<Duration, String> {
+    let secs: f64 = s.parse().map_err(|e| format!("{e}"))?;
+    match Duration::try_from_secs_f64(secs) {
+        Ok(d) if d <= Duration::from_secs(MAX_SECS) => Ok(d),
+        _ => Err(format!("must be between 0 and {MAX_SECS} seconds")),
+    }
+}
+
+// `--duration`: like `parse_secs`, but zero would measure nothing
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    match parse_secs(s)? {
+        Duration::ZERO => Err("must be more than 0 seconds".to_string()),
+        d => Ok(d),
+    }
+}
+
+// Decides whether another request may start, shared by all senders
+struct Budget {
+    warmup_end: Instant,
+    end: Option<Instant>,
+    max_requests: Option<u64>,
+    started: AtomicU64,
+}
+
+impl Budget {
+    fn new(opts: &BenchOptions) -> Self {
+        let warmup_end = Instant::now() + opts.warmup;
+        let (end, max_requests) = match opts.limit {
+            Limit::Duration(d) => (Some(warmup_end + d), None),
+            Limit::Requests(n) => (None, Some(n)),
+        };
+        Self {
+            warmup_end,
+            end,
+            max_requests,
+            started: AtomicU64::new(0),
+        }
+    }
+
+    fn take(&self, at: Instant) -> bool {
+        if self.end.is_some_and(|end| at >= end) {
+            return false;
+        }
+        if at < self.warmup_end {
+            return true;
+        }
+        let n = self.started.fetch_add(1, Ordering::Relaxed);
+        self.max_requests.is_none_or(|max| n < max)
+    }
+}
+
+struct Sample {
+    // When the request was meant to start: for the open model that's its
+    // slot in the schedule, so queueing delay counts as latency
+    start: Instant,
+    latency: Duration,
+    result: Result<(u16, u64), FetchError>,
+}
+
+async fn one(client: &Client, url: &str, limit: Duration) -> Result<(u16, u64), FetchError> {
+    let fut = async {
+        let resp = client
+            .get(url)
+            .send()
+            .await
+            .map_err(FetchError::from_reqwest)?;
+        let status = resp.status().as_u16();
+        let mut bytes = 0;
+        let mut body = resp.bytes_stream();
+        while let Some(chunk) = body.next().await {
+            bytes += chunk.map_err(FetchError::Body)?.len() as u64;
+        }
+        Ok((status, bytes))
+    };
+    timeout(limit, fut)
+        .await
+        .unwrap_or(Err(FetchError::Timeout))
+}
+
+fn error_kind(e: &FetchError) -> &'static str {
+    match e {
+        FetchError::Connect(_) => "connect",
+        FetchError::Timeout => "timeout",
+        FetchError::Body(_) => "body",
+        _ => "request",
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct LatencyMs {
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Report {
+    pub model: String,
+    // Measured requests (warmup excluded), failed ones included
+    pub requests: u64,
+    pub elapsed_ms: u64,
+    pub rps: f64,
+    pub bytes: u64,
+    // Open model only: requests skipped because too many were in flight
+    pub dropped: u64,
+    pub latency_ms: LatencyMs,
+    pub statuses: BTreeMap<u16, u64>,
+    pub errors: BTreeMap<String, u64>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let l = &self.latency_ms;
+        writeln!(f, "{:<10} {}", "model", self.model)?;
+        writeln!(
+            f,
+            "{:<10} {} in {:.2}s ({:.1} req/s, {} bytes)",
+            "requests",
+            self.requests,
+            self.elapsed_ms as f64 / 1000.0,
+            self.rps,
+            self.bytes
+        )?;
+        writeln!(
+            f,
+            "{:<10} p50 {:.2}ms  p90 {:.2}ms  p99 {:.2}ms  max {:.2}ms  mean {:.2}ms",
+            "latency", l.p50, l.p90, l.p99, l.max, l.mean
+        )?;
+        let join = |m: Vec<String>| {
+            if m.is_empty() {
+                "-".to_string()
+            } else {
+                m.join(", ")
+            }
+        };
+        let statuses = self.statuses.iter().map(|(k, v)| format!("{k}: {v}"));
+        writeln!(f, "{:<10} {}", "status", join(statuses.collect()))?;
+        let errors = self.errors.iter().map(|(k, v)| format!("{k}: {v}"));
+        write!(f, "{:<10} {}", "errors", join(errors.collect()))?;
+        if self.dropped > 0 {
+            write!(f, "\n{:<10} {}", "dropped", self.dropped)?;
+        }
+        Ok(())
+    }
+}
+
+// Load `url` per `opts` and summarize what came back
+pub async fn run(client: &Client, url: &str, opts: &BenchOptions) -> Report {
+    let budget = Arc::new(Budget::new(opts));
+    let (tx, mut rx) = mpsc::unbounded_channel::<Sample>();
+    let mut senders = JoinSet::new();
+    let dropped = Arc::new(AtomicU64::new(0));
+
+    let model = match opts.model {
+        Model::Closed { concurrency } => {
+            for _ in 0..concurrency.max(1) {
+                let (client, url, tx, budget) =
+                    (client.clone(), url.to_string(), tx.clone(), budget.clone());
+                let limit = opts.timeout;
+                senders.spawn(async move {
+                    loop {
+                        let start = Instant::now();
+                        if !budget.take(start) {
+                            break;
+                        }
+                        let result = one(&client, &url, limit).await;
+                        let latency = start.elapsed();
+                        let _ = tx.send(Sample {
+                            start,
+                            latency,
+                            result,
+                        });
+                    }
+                });
+            }
+            format!("closed (concurrency {})", concurrency.max(1))
+        }
+        Model::Open {
+            rate,
+            max_in_flight,
+        } => {
+            let (client, url, tx, budget) =
+                (client.clone(), url.to_string(), tx.clone(), budget.clone());
+            let (limit, dropped) = (opts.timeout, dropped.clone());
+            let every = limit::period(rate).unwrap_or(Duration::from_secs(1));
+            senders.spawn(async move {
+                let slots = Arc::new(Semaphore::new(max_in_flight.max(1)));
+                let mut requests = JoinSet::new();
+                let mut ticks = interval_at(Instant::now(), every);
+                // Late ticks fire right away: the schedule doesn't slip
+                ticks.set_missed_tick_behavior(MissedTickBehavior::Burst);
+                loop {
+                    let start = ticks.tick().await;
+                    if !budget.take(start) {
+                        break;
+                    }
+                    let Ok(slot) = slots.clone().try_acquire_owned() else {
+                        dropped.fetch_add(1, Ordering::Relaxed);
+                        continue;
+                    };
+                    let (client, url, tx) = (client.clone(), url.clone(), tx.clone());
+                    requests.spawn(async move {
+                        let result = one(&client, &url, limit).await;
+                        let latency = start.elapsed();
+                        drop(slot);
+                        let _ = tx.send(Sample {
+                            start,
+                            latency,
+                            result,
+                        });
+                    });
+                }
+                while requests.join_next().await.is_some() {}
+            });
+            format!("open ({rate} req/s, max {max_in_flight} in flight)")
+        }
+    };
+    // The channel closes once every sender is done
+    drop(tx);
+
+    let mut hist = Histogram::default();
+    let mut statuses = BTreeMap::new();
+    let mut errors = BTreeMap::new();
+    let mut bytes = 0;
+    let mut last = budget.warmup_end;
+    while let Some(s) = rx.recv().await {
+        if s.start < budget.warmup_end {
+            continue;
+        }
+        hist.record(s.latency.as_micros() as u64);
+        last = last.max(s.start + s.latency);
+        match s.result {
+            Ok((status, n)) => {
+                *statuses.entry(status).or_insert(0) += 1;
+                bytes += n;
+            }
+            Err(e) => *errors.entry(error_kind(&e).to_string()).or_insert(0) += 1,
+        }
+    }
+    while senders.join_next().await.is_some() {}
+
+    let elapsed = last.saturating_duration_since(budget.warmup_end);
+    let ms = |us: u64| (us as f64 / 10.0).round() / 100.0;
+    Report {
+        model,
+        requests: hist.count(),
+        elapsed_ms: elapsed.as_millis() as u64,
+        rps: hist.count() as f64 / elapsed.as_secs_f64().max(1e-6),
+        bytes,
+        dropped: dropped.load(Ordering::Relaxed),
+        latency_ms: LatencyMs {
+            p50: ms(hist.percentile(50.0)),
+            p90: ms(hist.percentile(90.0)),
+            p99: ms(hist.percentile(99.0)),
+            max: ms(hist.max()),
+            mean: (hist.mean() / 10.0).round() / 100.0,
+        },
+        statuses,
+        errors,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::test_server::{Route, TestServer};
+
+    #[test]
+    fn rejects_unusable_durations() {
+        assert_eq!(parse_secs("0"), Ok(Duration::ZERO));
+        assert_eq!(parse_duration("1.5"), Ok(Duration::from_millis(1500)));
+        for bad in ["-1", "NaN", "inf", "1e30", "soon"] {
+            assert!(parse_secs(bad).is_err(), "{bad}");
+        }
+        assert!(parse_duration("0").is_err());
+    }
+
+    #[tokio::test]
+    async fn closed_model_sends_exactly_the_requested_count() {
+        let srv = TestServer::start(HashMap::from([("/".to_string(), Route::ok("pong"))])).await;
+        let opts = BenchOptions {
+            model: Model::Closed { concurrency: 4 },
+            limit: Limit::Requests(40),
+            warmup: Duration::from_millis(50),
+            timeout: Duration::from_secs(5),
+        };
+        let report = run(&Client::new(), &srv.url("/"), &opts).await;
+        assert_eq!(report.requests, 40);
+        assert_eq!(report.statuses, BTreeMap::from([(200, 40)]));
+        assert_eq!(report.bytes, 160);
+        // Warmup requests hit the server but not the report
+        assert!(srv.requests().len() > 40);
+        assert!(report.latency_ms.p50 <= report.latency_ms.p99);
+        assert!(report.latency_ms.p99 <= report.latency_ms.max);
+    }
+
+    #[tokio::test]
+    async fn open_model_keeps_its_schedule_and_counts_errors() {
+        let srv = TestServer::start(HashMap::new()).await;
+        let opts = BenchOptions {
+            model: Model::Open {
+                rate: 200.0,
+                max_in_flight: 16,
+            },
+            limit: Limit::Requests(20),
+            warmup: Duration::ZERO,
+            timeout: Duration::from_secs(5),
+        };
+        let report = run(&Client::new(), &srv.url("/missing"), &opts).await;
+        assert_eq!(report.statuses, BTreeMap::from([(404, 20)]));
+        // 20 requests 5ms apart: the last starts ~95ms after the first
+        assert!(report.elapsed_ms >= 95, "{report:?}");
+        assert!(report.to_string().contains("404: 20"));
+    }
+}
@@ -0,0 +1,348 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use futures::StreamExt;
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+use tokio::time::timeout;
+
+use crate::retry::FetchError;
+
+// `<name>.<pid>.<n>.tmp` next to `path`: two fetches of the same URL (or
+// two processes sharing the cache) never write the same temp file
+fn tmp_path(path: &Path) -> PathBuf {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(
+        "{name}.{}.{}.tmp",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+// A temp file that is removed again unless it was renamed into place, on
+// every way out: errors, and futures dropped halfway (by a timeout, say)
+struct TmpFile {
+    path: PathBuf,
+    persisted: bool,
+}
+
+impl TmpFile {
+    fn next_to(path: &Path) -> Self {
+        Self {
+            path: tmp_path(path),
+            persisted: false,
+        }
+    }
+
+    async fn persist(mut self, to: &Path) -> std::io::Result<()> {
+        tokio::fs::rename(&self.path, to).await?;
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for TmpFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+// What's kept next to each cached body
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Entry {
+    pub url: String,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub len: u64,
+    // Unix seconds of the last download or successful revalidation
+    pub checked_at: u64,
+}
+
+// Where the body came from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    Network,
+    // The server answered 304 to our conditional request
+    Revalidated,
+    // Offline mode: no request made at all
+    Offline,
+}
+
+// A directory with `<sha256(url)>.json` + `<sha256(url)>.body` per URL
+pub struct Cache {
+    dir: PathBuf,
+    // Never touch the network; a miss is an error
+    pub offline: bool,
+}
+
+impl Cache {
+    pub fn open(dir: &Path, offline: bool) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create cache dir {}", dir.display()))?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            offline,
+        })
+    }
+
+    fn paths(&self, url: &str) -> (PathBuf, PathBuf) {
+        let key: String = Sha256::digest(url.as_bytes())
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        (
+            self.dir.join(format!("{key}.json")),
+            self.dir.join(format!("{key}.body")),
+        )
+    }
+
+    // The entry for `url`, if both its files are there
+    pub async fn get(&self, url: &str) -> Option<Entry> {
+        let (meta, body) = self.paths(url);
+        let json = tokio::fs::read(&meta).await.ok()?;
+        let entry: Entry = serde_json::from_slice(&json).ok()?;
+        let on_disk = tokio::fs::metadata(&body).await.ok()?.len();
+        (entry.url == url && entry.len == on_disk).then_some(entry)
+    }
+
+    async fn save_entry(&self, entry: &Entry) -> Result<()> {
+        let (meta, _) = self.paths(&entry.url);
+        let tmp = TmpFile::next_to(&meta);
+        tokio::fs::write(&tmp.path, serde_json::to_vec_pretty(entry)?).await?;
+        tmp.persist(&meta)
+            .await
+            .with_context(|| format!("failed to write {}", meta.display()))
+    }
+
+    // Fetch `url` through the cache: a conditional GET when we hold
+    // validators, a plain one otherwise. Same non-2xx check and 10s timeout
+    // as `fetch_task`. Returns the body length and where it came from.
+    pub async fn fetch(&self, client: &Client, url: &str) -> Result<(u64, Source)> {
+        let cached = self.get(url).await;
+        if self.offline {
+            return match cached {
+                Some(e) => Ok((e.len, Source::Offline)),
+                None => anyhow::bail!("{url} -> not in cache (offline)"),
+            };
+        }
+        timeout(
+            Duration::from_secs(10),
+            self.revalidate(client, url, cached),
+        )
+        .await
+        .map_err(|_| anyhow::Error::new(FetchError::Timeout))
+        .flatten()
+    }
+
+    async fn revalidate(
+        &self,
+        client: &Client,
+        url: &str,
+        cached: Option<Entry>,
+    ) -> Result<(u64, Source)> {
+        let mut req = client.get(url);
+        if let Some(e) = &cached {
+            if let Some(etag) = &e.etag {
+                req = req.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(lm) = &e.last_modified {
+                req = req.header(IF_MODIFIED_SINCE, lm);
+            }
+        }
+        let resp = req
+            .send()
+            .await
+            .with_context(|| format!("request failed: GET {url}"))?;
+
+        let status = resp.status();
+        if status == StatusCode::NOT_MODIFIED
+            && let Some(mut entry) = cached
+        {
+            entry.checked_at = now();
+            self.save_entry(&entry).await?;
+            return Ok((entry.len, Source::Revalidated));
+        }
+        if !status.is_success() {
+            anyhow::bail!("{url} -> HTTP {status}");
+        }
+
+        let header = |name| {
+            resp.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+        let (etag, last_modified) = (header(ETAG), header(LAST_MODIFIED));
+        let headers = resp
+            .headers()
+            .iter()
+            .filter_map(|(k, v)| Some((k.to_string(), v.to_str().ok()?.to_string())))
+            .collect();
+
+        // Stream into a temp file so a broken download never replaces a
+        // good cached body
+        let (_, body_path) = self.paths(url);
+        let tmp = TmpFile::next_to(&body_path);
+        let mut file = tokio::fs::File::create(&tmp.path)
+            .await
+            .with_context(|| format!("failed to create {}", tmp.path.display()))?;
+        let mut len = 0;
+        let mut body = resp.bytes_stream();
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.with_context(|| format!("read body failed: {url}"))?;
+            file.write_all(&chunk).await?;
+            len += chunk.len() as u64;
+        }
+        file.flush().await?;
+        drop(file);
+        tmp.persist(&body_path).await?;
+
+        self.save_entry(&Entry {
+            url: url.to_string(),
+            status: status.as_u16(),
+            headers,
+            etag,
+            last_modified,
+            len,
+            checked_at: now(),
+        })
+        .await?;
+        Ok((len, Source::Network))
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::test_server::{Route, TestServer};
+
+    #[tokio::test]
+    async fn revalidates_and_serves_offline() {
+        let srv = TestServer::start(HashMap::new()).await;
+        srv.set_route(
+            "/doc",
+            Route {
+                headers: vec![
+                    ("ETag".into(), "\"v1\"".into()),
+                    (
+                        "Last-Modified".into(),
+                        "Sun, 06 Nov 1994 08:49:37 GMT".into(),
+                    ),
+                ],
+                ..Route::ok("hello")
+            },
+        );
+        let dir = std::env::temp_dir().join(format!("lesson05-cache-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let cache = Cache::open(&dir, false).unwrap();
+        let client = Client::new();
+        let url = srv.url("/doc");
+
+        assert_eq!(
+            cache.fetch(&client, &url).await.unwrap(),
+            (5, Source::Network)
+        );
+        let entry = cache.get(&url).await.unwrap();
+        assert_eq!(entry.etag.as_deref(), Some("\"v1\""));
+        assert!(entry.headers.iter().any(|(k, _)| k == "last-modified"));
+
+        srv.set_route(
+            "/doc",
+            Route {
+                status: 304,
+                ..Route::default()
+            },
+        );
+        assert_eq!(
+            cache.fetch(&client, &url).await.unwrap(),
+            (5, Source::Revalidated)
+        );
+        assert!(srv.requests()[1].contains("if-none-match=\"v1\""));
+        assert_eq!(std::fs::read(cache.paths(&url).1).unwrap(), b"hello");
+
+        let offline = Cache::open(&dir, true).unwrap();
+        assert_eq!(
+            offline.fetch(&client, &url).await.unwrap(),
+            (5, Source::Offline)
+        );
+        assert!(offline.fetch(&client, &srv.url("/other")).await.is_err());
+        assert_eq!(srv.requests().len(), 2);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn duplicate_urls_fetch_concurrently() {
+        let body = "x".repeat(256 * 1024);
+        let srv = TestServer::start(HashMap::from([(
+            "/big".to_string(),
+            Route::ok(body.as_str()),
+        )]))
+        .await;
+        let dir = std::env::temp_dir().join(format!("lesson05-cache-dup-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let cache = Cache::open(&dir, false).unwrap();
+        let client = Client::new();
+        let url = srv.url("/big");
+
+        let fetches = (0..8).map(|_| cache.fetch(&client, &url));
+        for res in futures::future::join_all(fetches).await {
+            assert_eq!(res.unwrap().0, body.len() as u64);
+        }
+        assert_eq!(std::fs::read(cache.paths(&url).1).unwrap(), body.as_bytes());
+        // Only the entry's two files: no temp file was shared or left behind
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn abandoned_downloads_leave_no_temp_files() {
+        let srv = TestServer::start(HashMap::from([
+            (
+                "/slow".to_string(),
+                Route {
+                    stall: Some(Duration::from_secs(60)),
+                    ..Route::ok("late")
+                },
+            ),
+            (
+                "/cut".to_string(),
+                Route {
+                    cut_after: Some(10),
+                    reset: true,
+                    ..Route::ok("x".repeat(10_000))
+                },
+            ),
+        ]))
+        .await;
+        let dir = std::env::temp_dir().join(format!("lesson05-cache-tmp-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let cache = Cache::open(&dir, false).unwrap();
+        let client = Client::new();
+
+        // Dropped mid-body, the way the 10s timeout drops it
+        let slow = srv.url("/slow");
+        let fetch = cache.fetch(&client, &slow);
+        assert!(timeout(Duration::from_millis(300), fetch).await.is_err());
+        assert!(cache.fetch(&client, &srv.url("/cut")).await.is_err());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,103 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use reqwest::{Client, Proxy};
+
+pub const DEFAULT_USER_AGENT: &str =
+    concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum HttpVersion {
+    // HTTP/2 when negotiated via ALPN, else HTTP/1.1
+    #[default]
+    Auto,
+    Http1,
+    // HTTP/2 without negotiation, also over plain http
+    Http2,
+}
+
+// Everything the shared `Client` is built from
+#[derive(Clone, Debug)]
+pub struct ClientConfig {
+    pub pool_max_idle_per_host: usize,
+    pub pool_idle_timeout: Duration,
+    pub http: HttpVersion,
+    pub user_agent: String,
+    // Send every request through this proxy (http://, https:// or socks5://)
+    pub proxy: Option<String>,
+    // Resolve these hosts to fixed addresses instead of asking DNS; the
+    // port still comes from the URL
+    pub resolve: Vec<(String, IpAddr)>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            pool_max_idle_per_host: 32,
+            pool_idle_timeout: Duration::from_secs(15),
+            http: HttpVersion::Auto,
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            proxy: None,
+            resolve: Vec::new(),
+        }
+    }
+}
+
+impl ClientConfig {
+    pub fn build(&self) -> Result<Client> {
+        let mut builder = Client::builder()
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .pool_idle_timeout(self.pool_idle_timeout)
+            .user_agent(&self.user_agent);
+        builder = match self.http {
+            HttpVersion::Auto => builder,
+            HttpVersion::Http1 => builder.http1_only(),
+            HttpVersion::Http2 => builder.http2_prior_knowledge(),
+        };
+        if let Some(proxy) = &self.proxy {
+            let proxy = Proxy::all(proxy).with_context(|| format!("bad proxy URL: {proxy}"))?;
+            builder = builder.proxy(proxy);
+        }
+        for (host, ip) in &self.resolve {
+            builder = builder.resolve(host, SocketAddr::new(*ip, 0));
+        }
+        builder.build().context("failed to build HTTP client")
+    }
+}
+
+// `HOST=IP`, as given to `--resolve`
+pub fn parse_resolve(s: &str) -> Result<(String, IpAddr), String> {
+    let (host, ip) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected HOST=IP, got `{s}`"))?;
+    let ip = ip
+        .trim_matches(['[', ']'])
+        .parse()
+        .map_err(|e| format!("bad IP in `{s}`: {e}"))?;
+    Ok((host.to_string(), ip))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::fetch::fetch_len;
+    use crate::test_server::{Route, TestServer};
+
+    #[tokio::test]
+    async fn dns_overrides_reach_the_local_server() {
+        let srv = TestServer::start(HashMap::from([("/".to_string(), Route::ok("hi"))])).await;
+        let config = ClientConfig {
+            resolve: vec![parse_resolve("fixture.invalid=127.0.0.1").unwrap()],
+            http: HttpVersion::Http1,
+            ..ClientConfig::default()
+        };
+        let url = format!("http://fixture.invalid:{}/", srv.addr.port());
+        assert_eq!(fetch_len(&config.build().unwrap(), &url).await.unwrap(), 2);
+
+        assert!(parse_resolve("fixture.invalid").is_err());
+        assert!(parse_resolve("h=[::1]").is_ok());
+    }
+}
@@ -0,0 +1,541 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::Write;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use futures::StreamExt;
+use futures::future::{Either, FutureExt};
+use futures::stream::FuturesUnordered;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Client, Url};
+use serde::Serialize;
+use tokio::time::{Instant, sleep_until, timeout};
+
+use crate::limit::host_key;
+use crate::robots::Robots;
+
+// Only this much of a robots.txt is read, as Google does
+const MAX_ROBOTS_BYTES: usize = 500 * 1024;
+
+pub struct CrawlOptions {
+    // Links are followed this many hops away from a seed (seeds are depth 0)
+    pub max_depth: usize,
+    // Stop scheduling once this many pages have been fetched
+    pub max_pages: usize,
+    // Hosts (and their subdomains) we may visit; empty = the seeds' hosts
+    pub allowed_domains: Vec<String>,
+    // Minimum gap between two requests to the same host
+    pub per_host_delay: Duration,
+    // Pages in flight across all hosts
+    pub concurrency: usize,
+    pub respect_robots: bool,
+    // Matched against robots.txt `User-agent` groups
+    pub user_agent: String,
+    pub timeout: Duration,
+    // HTML bodies are only parsed up to this size
+    pub max_html_bytes: usize,
+}
+
+impl Default for CrawlOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: 2,
+            max_pages: 100,
+            allowed_domains: Vec::new(),
+            per_host_delay: Duration::from_millis(500),
+            concurrency: 4,
+            respect_robots: true,
+            user_agent: "lesson05_tokio_async".to_string(),
+            timeout: Duration::from_secs(10),
+            max_html_bytes: 2 * 1024 * 1024,
+        }
+    }
+}
+
+// One line of the JSONL report
+#[derive(Serialize, Debug)]
+pub struct PageReport {
+    pub url: String,
+    pub depth: usize,
+    pub status: Option<u16>,
+    pub bytes: u64,
+    pub latency_ms: u64,
+    // Normalized, deduplicated links found on the page (any domain)
+    pub outlinks: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct CrawlStats {
+    pub pages: usize,
+    pub errors: usize,
+    // Skipped because robots.txt disallows them
+    pub blocked: usize,
+}
+
+struct Host {
+    // `None` while its robots.txt is still being fetched
+    robots: Option<Robots>,
+    // URLs that turned up before the robots.txt did
+    waiting: Vec<(Url, usize)>,
+    // Earliest time the next request to this host may start
+    next_slot: Instant,
+}
+
+// What finished among the requests in flight
+enum Done {
+    Page(Page),
+    // robots.txt for a host key
+    Robots(String, Robots),
+}
+
+// Breadth-first crawl from `seeds`, writing one JSON line per fetched page
+// to `out` as pages finish
+pub async fn crawl<W: Write>(
+    client: &Client,
+    seeds: &[String],
+    opts: &CrawlOptions,
+    out: &mut W,
+) -> Result<CrawlStats> {
+    let mut allowed = opts.allowed_domains.clone();
+    let mut frontier = VecDeque::new();
+    let mut seen = HashSet::new();
+    for seed in seeds {
+        let url = normalize(Url::parse(seed).with_context(|| format!("bad seed URL: {seed}"))?)
+            .with_context(|| format!("not an http(s) URL: {seed}"))?;
+        if opts.allowed_domains.is_empty()
+            && let Some(host) = url.host_str()
+        {
+            allowed.push(host.to_string());
+        }
+        if seen.insert(url.to_string()) {
+            frontier.push_back((url, 0));
+        }
+    }
+
+    let mut stats = CrawlStats::default();
+    let mut hosts: HashMap<String, Host> = HashMap::new();
+    let mut in_flight = FuturesUnordered::new();
+    let mut scheduled = 0;
+
+    loop {
+        while in_flight.len() < opts.concurrency.max(1) && scheduled < opts.max_pages {
+            let Some((url, depth)) = frontier.pop_front() else {
+                break;
+            };
+            let key = host_key(&url);
+            // A new host's first URL waits for its robots.txt, which runs
+            // alongside the pages already in flight instead of ahead of them
+            let host = hosts.entry(key.clone()).or_insert_with(|| {
+                let robots = if opts.respect_robots {
+                    let (key, url) = (key.clone(), url.clone());
+                    in_flight.push(Either::Right(async move {
+                        Done::Robots(key, fetch_robots(client, &url, opts).await)
+                    }));
+                    None
+                } else {
+                    Some(Robots::allow_all())
+                };
+                Host {
+                    robots,
+                    waiting: Vec::new(),
+                    // The robots.txt request counts against the host's delay too
+                    next_slot: Instant::now() + opts.per_host_delay,
+                }
+            });
+            let Some(robots) = &host.robots else {
+                host.waiting.push((url, depth));
+                continue;
+            };
+
+            let mut path = url.path().to_string();
+            if let Some(q) = url.query() {
+                path = format!("{path}?{q}");
+            }
+            if !robots.allowed(&path) {
+                stats.blocked += 1;
+                continue;
+            }
+
+            let start = host.next_slot.max(Instant::now());
+            host.next_slot = start + opts.per_host_delay;
+            scheduled += 1;
+            in_flight.push(Either::Left(
+                fetch_page(client, url, depth, start, opts).map(Done::Page),
+            ));
+        }
+
+        let page = match in_flight.next().await {
+            None => break,
+            Some(Done::Page(page)) => page,
+            // The host's held-back URLs go back to the front, in order
+            Some(Done::Robots(key, robots)) => {
+                let host = hosts.get_mut(&key).expect("added when its fetch started");
+                host.robots = Some(robots);
+                for queued in host.waiting.drain(..).rev() {
+                    frontier.push_front(queued);
+                }
+                continue;
+            }
+        };
+        stats.pages += 1;
+        if page.report.error.is_some() {
+            stats.errors += 1;
+        }
+        serde_json::to_writer(&mut *out, &page.report)?;
+        out.write_all(b"\n")?;
+
+        if page.report.depth < opts.max_depth {
+            for link in page.links {
+                if in_domains(&link, &allowed) && seen.insert(link.to_string()) {
+                    frontier.push_back((link, page.report.depth + 1));
+                }
+            }
+        }
+    }
+    out.flush()?;
+    Ok(stats)
+}
+
+struct Page {
+    report: PageReport,
+    links: Vec<Url>,
+}
+
+async fn fetch_page(
+    client: &Client,
+    url: Url,
+    depth: usize,
+    not_before: Instant,
+    opts: &CrawlOptions,
+) -> Page {
+    sleep_until(not_before).await;
+    let started = Instant::now();
+    let res = timeout(opts.timeout, get_page(client, &url, opts.max_html_bytes)).await;
+    let latency_ms = started.elapsed().as_millis() as u64;
+
+    let mut report = PageReport {
+        url: url.to_string(),
+        depth,
+        status: None,
+        bytes: 0,
+        latency_ms,
+        outlinks: Vec::new(),
+        error: None,
+    };
+    let mut links = Vec::new();
+    match res {
+        Err(_) => report.error = Some(format!("timed out after {:?}", opts.timeout)),
+        Ok(Err(e)) => report.error = Some(format!("{e:#}")),
+        Ok(Ok(fetched)) => {
+            report.status = Some(fetched.status);
+            report.bytes = fetched.bytes;
+            if let Some(html) = &fetched.html {
+                let mut found = HashSet::new();
+                for href in extract_links(html) {
+                    if let Some(link) = fetched.base.join(&href).ok().and_then(normalize)
+                        && found.insert(link.to_string())
+                    {
+                        links.push(link);
+                    }
+                }
+                report.outlinks = links.iter().map(Url::to_string).collect();
+            }
+        }
+    }
+    Page { report, links }
+}
+
+struct Fetched {
+    status: u16,
+    bytes: u64,
+    // Final URL after redirects, for resolving relative links
+    base: Url,
+    // Only for successful text/html responses
+    html: Option<String>,
+}
+
+// GET one page. Non-2xx is not an error here: the status goes in the report.
+async fn get_page(client: &Client, url: &Url, max_html: usize) -> Result<Fetched> {
+    let resp = client
+        .get(url.clone())
+        .send()
+        .await
+        .with_context(|| format!("request failed: GET {url}"))?;
+
+    let status = resp.status();
+    let base = resp.url().clone();
+    let is_html = status.is_success()
+        && resp
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|ct| ct.contains("text/html"));
+
+    // Count every byte, but only keep (the start of) HTML bodies
+    let mut bytes = 0;
+    let mut kept = Vec::new();
+    let mut body = resp.bytes_stream();
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.with_context(|| format!("read body failed: {url}"))?;
+        bytes += chunk.len() as u64;
+        if is_html && kept.len() < max_html {
+            let room = max_html - kept.len();
+            kept.extend_from_slice(&chunk[..chunk.len().min(room)]);
+        }
+    }
+
+    Ok(Fetched {
+        status: status.as_u16(),
+        bytes,
+        base,
+        html: is_html.then(|| String::from_utf8_lossy(&kept).into_owned()),
+    })
+}
+
+// robots.txt for the URL's origin; a missing or unreachable one allows all
+async fn fetch_robots(client: &Client, url: &Url, opts: &CrawlOptions) -> Robots {
+    let Ok(robots_url) = url.join("/robots.txt") else {
+        return Robots::allow_all();
+    };
+    let fetch = async {
+        let resp = client.get(robots_url).send().await?.error_for_status()?;
+        let mut kept = Vec::new();
+        let mut body = resp.bytes_stream();
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk?;
+            let room = MAX_ROBOTS_BYTES - kept.len();
+            kept.extend_from_slice(&chunk[..chunk.len().min(room)]);
+            if kept.len() == MAX_ROBOTS_BYTES {
+                break;
+            }
+        }
+        Ok::<_, reqwest::Error>(String::from_utf8_lossy(&kept).into_owned())
+    };
+    match timeout(opts.timeout, fetch).await {
+        Ok(Ok(text)) => Robots::parse(&text, &opts.user_agent),
+        _ => Robots::allow_all(),
+    }
+}
+
+// Canonical form used for dedupe: http(s) only, no fragment, query pairs
+// sorted, no empty `?`. Scheme/host case and default ports are already
+// normalized by `Url` itself.
+pub fn normalize(mut url: Url) -> Option<Url> {
+    if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+        return None;
+    }
+    url.set_fragment(None);
+    match url.query() {
+        Some("") => url.set_query(None),
+        Some(_) => {
+            let mut pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+            pairs.sort();
+            url.query_pairs_mut().clear().extend_pairs(pairs);
+        }
+        None => {}
+    }
+    Some(url)
+}
+
+// Same host as an allowed domain, or a subdomain of one
+fn in_domains(url: &Url, allowed: &[String]) -> bool {
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    allowed.iter().any(|d| {
+        host.eq_ignore_ascii_case(d)
+            || host
+                .to_ascii_lowercase()
+                .ends_with(&format!(".{}", d.to_ascii_lowercase()))
+    })
+}
+
+// `href` values of `<a>` and `<area>` tags, entity `&amp;` decoded. Not a
+// real HTML parser: good enough for well-formed pages, skips the rest.
+pub fn extract_links(html: &str) -> Vec<String> {
+    let lower = html.to_ascii_lowercase();
+    let mut links = Vec::new();
+    let mut pos = 0;
+    while let Some(start) = lower[pos..].find('<').map(|i| pos + i) {
+        let end = lower[start..].find('>').map_or(lower.len(), |i| start + i);
+        let tag = &lower[start + 1..end];
+        pos = end;
+        if !(tag.starts_with("a ") || tag.starts_with("area ")) {
+            continue;
+        }
+        if let Some(href) = attr(tag, &html[start + 1..end], "href") {
+            let href = href.trim().replace("&amp;", "&");
+            let lowered = href.to_ascii_lowercase();
+            if !href.is_empty()
+                && !lowered.starts_with("javascript:")
+                && !lowered.starts_with("mailto:")
+            {
+                links.push(href);
+            }
+        }
+    }
+    links
+}
+
+// Value of attribute `name` in a tag; `lower` is the lowercased tag used
+// for lookup, `orig` the same bytes with case preserved for the value
+fn attr<'a>(lower: &str, orig: &'a str, name: &str) -> Option<&'a str> {
+    let mut from = 0;
+    while let Some(i) = lower[from..].find(name).map(|i| from + i) {
+        from = i + name.len();
+        // Must be a whole attribute name followed by `=`
+        let before_ok = i == 0 || lower.as_bytes()[i - 1].is_ascii_whitespace();
+        let rest = lower[from..].trim_start();
+        if !before_ok || !rest.starts_with('=') {
+            continue;
+        }
+        let value_at = lower.len() - rest.len() + 1;
+        let value = orig[value_at..].trim_start();
+        return match value.chars().next()? {
+            q @ ('"' | '\'') => value[1..].split(q).next(),
+            _ => value.split(|c: char| c.is_ascii_whitespace()).next(),
+        };
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::test_server::{Route, TestServer};
+
+    fn html(body: &str) -> Route {
+        Route {
+            headers: vec![("Content-Type".into(), "text/html; charset=utf-8".into())],
+            ..Route::ok(body)
+        }
+    }
+
+    #[test]
+    fn extracts_and_normalizes_links() {
+        let page = r#"<A HREF="/a#top">a</A> <a class=x href='b?z=1&amp;y=2'>
+            <a href=mailto:x@y.z> <area href=/c> <link href="/style.css">"#;
+        assert_eq!(extract_links(page), vec!["/a#top", "b?z=1&y=2", "/c"]);
+
+        let base = Url::parse("http://Example.com:80/dir/").unwrap();
+        let n = |s: &str| normalize(base.join(s).unwrap()).unwrap().to_string();
+        assert_eq!(n("/a#top"), "http://example.com/a");
+        assert_eq!(n("b?z=1&y=2"), "http://example.com/dir/b?y=2&z=1");
+        assert_eq!(n("?"), "http://example.com/dir/");
+    }
+
+    #[tokio::test]
+    async fn crawls_fixture_site_within_limits() {
+        let srv = TestServer::start(HashMap::new()).await;
+        srv.set_route(
+            "/",
+            html(
+                r#"<a href="/a">a</a> <a href="/b#x">b</a> <a href="/private/x">p</a>
+                <a href="http://elsewhere.invalid/">ext</a>"#,
+            ),
+        );
+        srv.set_route(
+            "/a",
+            html(r#"<a href="/">home</a> <a href="/a/deep">deep</a>"#),
+        );
+        srv.set_route("/b", Route::ok("not html"));
+        srv.set_route("/a/deep", html(r#"<a href="/too-deep">x</a>"#));
+        srv.set_route("/private/x", html("secret"));
+        srv.set_route(
+            "/robots.txt",
+            Route::ok("User-agent: *\nDisallow: /private\n"),
+        );
+
+        let opts = CrawlOptions {
+            per_host_delay: Duration::from_millis(20),
+            ..CrawlOptions::default()
+        };
+        let mut out = Vec::new();
+        let started = Instant::now();
+        let stats = crawl(&Client::new(), &[srv.url("/")], &opts, &mut out)
+            .await
+            .unwrap();
+
+        let pages: Vec<serde_json::Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        let mut urls: Vec<&str> = pages.iter().map(|p| p["url"].as_str().unwrap()).collect();
+        urls.sort();
+        let expect = ["/", "/a", "/a/deep", "/b"].map(|p| srv.url(p));
+        assert_eq!(urls, expect.iter().map(String::as_str).collect::<Vec<_>>());
+        assert_eq!(stats.blocked, 1);
+        assert_eq!(stats.errors, 0);
+
+        let root = pages.iter().find(|p| p["depth"] == 0).unwrap();
+        assert_eq!(root["status"], 200);
+        assert_eq!(root["outlinks"].as_array().unwrap().len(), 4);
+
+        // robots.txt + 4 pages, 20ms apart on the one host
+        assert!(started.elapsed() >= Duration::from_millis(80));
+        let log = srv.requests();
+        assert_eq!(log[0], "GET /robots.txt");
+        assert!(
+            !log.iter()
+                .any(|r| r.contains("private") || r.contains("too-deep"))
+        );
+    }
+
+    #[tokio::test]
+    async fn slow_robots_txt_holds_up_only_its_own_host() {
+        let slow = TestServer::start(HashMap::from([
+            ("/".to_string(), html("slow")),
+            (
+                "/robots.txt".to_string(),
+                Route {
+                    stall: Some(Duration::from_millis(500)),
+                    ..Route::ok("User-agent: *\nDisallow:\n")
+                },
+            ),
+        ]))
+        .await;
+        let fast = TestServer::start(HashMap::from([("/".to_string(), html("fast"))])).await;
+
+        let opts = CrawlOptions {
+            per_host_delay: Duration::ZERO,
+            ..CrawlOptions::default()
+        };
+        let mut out = Vec::new();
+        let seeds = [slow.url("/"), fast.url("/")];
+        let stats = crawl(&Client::new(), &seeds, &opts, &mut out)
+            .await
+            .unwrap();
+        assert_eq!(stats.pages, 2);
+
+        // The fast host's page finished while the slow robots.txt was pending
+        let pages: Vec<serde_json::Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        let urls: Vec<&str> = pages.iter().map(|p| p["url"].as_str().unwrap()).collect();
+        assert_eq!(urls, [fast.url("/"), slow.url("/")]);
+    }
+
+    #[tokio::test]
+    async fn robots_txt_is_read_up_to_500_kib() {
+        let text = format!(
+            "User-agent: *\nDisallow: /a\n{}\nDisallow: /b\n",
+            "#".repeat(MAX_ROBOTS_BYTES)
+        );
+        let srv = TestServer::start(HashMap::from([(
+            "/robots.txt".to_string(),
+            Route::ok(text),
+        )]))
+        .await;
+        let url = Url::parse(&srv.url("/")).unwrap();
+        let robots = fetch_robots(&Client::new(), &url, &CrawlOptions::default()).await;
+        assert!(!robots.allowed("/a"));
+        assert!(robots.allowed("/b"));
+    }
+}
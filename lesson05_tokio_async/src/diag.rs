@@ -0,0 +1,325 @@
+// Per-phase timing for one GET, in the spirit of `curl -w`. reqwest hides
+// its connection setup, so this does each step by hand (DNS, TCP, TLS) and
+// then speaks HTTP over the finished stream with hyper's connection API.
+
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use http_body_util::{BodyExt, Empty};
+use hyper::body::Bytes;
+use hyper::header::{HOST, USER_AGENT};
+use hyper::{Request, Response};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use reqwest::Url;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::time::{Instant, timeout};
+use tokio_rustls::TlsConnector;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{self, RootCertStore};
+
+use crate::client::{ClientConfig, HttpVersion};
+
+// What one diagnosed fetch saw. Times are from the start of the fetch, as
+// curl reports them; a phase that was never reached stays `None`.
+#[derive(Debug)]
+pub struct Diagnosis {
+    pub url: String,
+    // Every address DNS (or --resolve) returned, in the order tried
+    pub resolved: Vec<IpAddr>,
+    // The address we actually connected to
+    pub remote: Option<SocketAddr>,
+    pub http_version: Option<String>,
+    pub tls_version: Option<String>,
+    pub tls_cipher: Option<String>,
+    pub status: Option<u16>,
+    pub size: u64,
+    pub namelookup: Option<Duration>,
+    pub connect: Option<Duration>,
+    // TLS handshake done; https only
+    pub appconnect: Option<Duration>,
+    // Response head received
+    pub starttransfer: Option<Duration>,
+    pub total: Duration,
+    // Set when a phase failed; the message names the phase
+    pub error: Option<anyhow::Error>,
+}
+
+impl Diagnosis {
+    fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            resolved: Vec::new(),
+            remote: None,
+            http_version: None,
+            tls_version: None,
+            tls_cipher: None,
+            status: None,
+            size: 0,
+            namelookup: None,
+            connect: None,
+            appconnect: None,
+            starttransfer: None,
+            total: Duration::ZERO,
+            error: None,
+        }
+    }
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let or_dash = |s: Option<String>| s.unwrap_or_else(|| "-".to_string());
+        let secs = |d: Option<Duration>| or_dash(d.map(|d| format!("{:.6}s", d.as_secs_f64())));
+        let resolved: Vec<String> = self.resolved.iter().map(IpAddr::to_string).collect();
+        let mut remote = or_dash(self.remote.map(|a| a.to_string()));
+        if !resolved.is_empty() {
+            remote += &format!(" (resolved: {})", resolved.join(", "));
+        }
+        let tls = match (&self.tls_version, &self.tls_cipher) {
+            (Some(v), Some(c)) => Some(format!("{v} {c}")),
+            (v, _) => v.clone(),
+        };
+
+        writeln!(f, "{}", self.url)?;
+        let mut row = |name: &str, value: String| writeln!(f, "  {name:<19} {value}");
+        row("remote_ip:", remote)?;
+        row("http_version:", or_dash(self.http_version.clone()))?;
+        row("tls:", or_dash(tls))?;
+        row("http_code:", or_dash(self.status.map(|s| s.to_string())))?;
+        row("size_download:", self.size.to_string())?;
+        row("time_namelookup:", secs(self.namelookup))?;
+        row("time_connect:", secs(self.connect))?;
+        row("time_appconnect:", secs(self.appconnect))?;
+        row("time_starttransfer:", secs(self.starttransfer))?;
+        row("time_total:", secs(Some(self.total)))?;
+        if let Some(e) = &self.error {
+            row("error:", format!("{e:#}"))?;
+        }
+        Ok(())
+    }
+}
+
+// GET `url` once on a fresh connection, timing each phase. Honours the
+// config's user agent, HTTP version and --resolve overrides; a proxy would
+// hide the very phases we're after, so it's never used. Failures (including
+// running past `limit`) end up in `Diagnosis::error`, never as an `Err`.
+pub async fn diagnose(config: &ClientConfig, url: &str, limit: Duration) -> Diagnosis {
+    let mut d = Diagnosis::new(url);
+    let start = Instant::now();
+    match timeout(limit, run(config, url, start, &mut d)).await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => d.error = Some(e),
+        Err(_) => d.error = Some(anyhow::anyhow!("timed out after {limit:?}")),
+    }
+    d.total = start.elapsed();
+    d
+}
+
+async fn run(config: &ClientConfig, url: &str, start: Instant, d: &mut Diagnosis) -> Result<()> {
+    let parsed = Url::parse(url).with_context(|| format!("bad URL: {url}"))?;
+    let https = match parsed.scheme() {
+        "http" => false,
+        "https" => true,
+        other => anyhow::bail!("unsupported scheme: {other}"),
+    };
+    let host = parsed
+        .host_str()
+        .with_context(|| format!("no host in {url}"))?
+        .trim_matches(['[', ']'])
+        .to_string();
+    let port = parsed.port_or_known_default().unwrap_or(80);
+
+    // DNS, unless the host is an address already or was pinned by --resolve
+    let pinned = config.resolve.iter().find(|(h, _)| *h == host);
+    let fixed = host.parse::<IpAddr>().ok().or(pinned.map(|(_, ip)| *ip));
+    d.resolved = match fixed {
+        Some(ip) => vec![ip],
+        None => tokio::net::lookup_host((host.as_str(), port))
+            .await
+            .with_context(|| format!("DNS lookup failed: {host}"))?
+            .map(|a| a.ip())
+            .collect(),
+    };
+    d.namelookup = Some(start.elapsed());
+
+    // TCP: addresses in turn until one accepts
+    let mut last_err = None;
+    let mut tcp = None;
+    for ip in &d.resolved {
+        let addr = SocketAddr::new(*ip, port);
+        match TcpStream::connect(addr).await {
+            Ok(s) => {
+                d.remote = Some(addr);
+                tcp = Some(s);
+                break;
+            }
+            Err(e) => last_err = Some(e),
+        }
+    }
+    let tcp = match (tcp, last_err) {
+        (Some(s), _) => s,
+        (None, Some(e)) => {
+            return Err(e).with_context(|| format!("connect failed: {host}:{port}"));
+        }
+        (None, None) => anyhow::bail!("DNS lookup failed: {host} has no addresses"),
+    };
+    d.connect = Some(start.elapsed());
+
+    // Origin-form target for HTTP/1.1; HTTP/2 wants the full URI
+    let path = match parsed.query() {
+        Some(q) => format!("{}?{q}", parsed.path()),
+        None => parsed.path().to_string(),
+    };
+    let authority = match parsed.port() {
+        Some(p) => format!("{}:{p}", parsed.host_str().unwrap_or_default()),
+        None => parsed.host_str().unwrap_or_default().to_string(),
+    };
+
+    if !https {
+        let h2 = config.http == HttpVersion::Http2;
+        let target = if h2 { url } else { path.as_str() };
+        return exchange(tcp, h2, target, &authority, config, start, d).await;
+    }
+
+    let mut tls = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .context("TLS setup failed")?
+    .with_root_certificates(native_roots()?)
+    .with_no_client_auth();
+    tls.alpn_protocols = match config.http {
+        HttpVersion::Auto => vec![b"h2".to_vec(), b"http/1.1".to_vec()],
+        HttpVersion::Http1 => vec![b"http/1.1".to_vec()],
+        HttpVersion::Http2 => vec![b"h2".to_vec()],
+    };
+    let name =
+        ServerName::try_from(host.clone()).with_context(|| format!("bad TLS name: {host}"))?;
+    let stream = TlsConnector::from(Arc::new(tls))
+        .connect(name, tcp)
+        .await
+        .with_context(|| format!("TLS handshake failed: {host}"))?;
+    d.appconnect = Some(start.elapsed());
+
+    let session = stream.get_ref().1;
+    d.tls_version = session
+        .protocol_version()
+        .map(|v| v.as_str().map_or_else(|| format!("{v:?}"), str::to_string));
+    d.tls_cipher = session
+        .negotiated_cipher_suite()
+        .map(|s| format!("{:?}", s.suite()));
+    let h2 = session.alpn_protocol() == Some(b"h2");
+    let target = if h2 { url } else { path.as_str() };
+    exchange(stream, h2, target, &authority, config, start, d).await
+}
+
+// The system trust store, which reqwest's default native-tls backend also
+// uses, so the handshake here accepts exactly what the real fetch accepts
+// (internal CAs included). Loaded once per process.
+fn native_roots() -> Result<RootCertStore> {
+    static ROOTS: OnceLock<RootCertStore> = OnceLock::new();
+    let roots = ROOTS.get_or_init(|| {
+        let mut store = RootCertStore::empty();
+        store.add_parsable_certificates(rustls_native_certs::load_native_certs().certs);
+        store
+    });
+    anyhow::ensure!(
+        !roots.is_empty(),
+        "no trusted root certificates found in the system store"
+    );
+    Ok(roots.clone())
+}
+
+// Send the GET over an established stream and drain the body
+async fn exchange<S>(
+    io: S,
+    h2: bool,
+    target: &str,
+    authority: &str,
+    config: &ClientConfig,
+    start: Instant,
+    d: &mut Diagnosis,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let io = TokioIo::new(io);
+    let mut req = Request::get(target).header(USER_AGENT, &config.user_agent);
+    if !h2 {
+        req = req.header(HOST, authority);
+    }
+    let req = req.body(Empty::<Bytes>::new())?;
+
+    let resp: Response<_> = if h2 {
+        let (mut sender, conn) = hyper::client::conn::http2::handshake(TokioExecutor::new(), io)
+            .await
+            .context("HTTP/2 handshake failed")?;
+        tokio::spawn(conn);
+        sender.send_request(req).await
+    } else {
+        let (mut sender, conn) = hyper::client::conn::http1::handshake(io)
+            .await
+            .context("HTTP/1.1 handshake failed")?;
+        tokio::spawn(conn);
+        sender.send_request(req).await
+    }
+    .with_context(|| format!("request failed: GET {}", d.url))?;
+    d.starttransfer = Some(start.elapsed());
+    d.status = Some(resp.status().as_u16());
+    d.http_version = Some(format!("{:?}", resp.version()));
+
+    let mut body = resp.into_body();
+    while let Some(frame) = body.frame().await {
+        let frame = frame.with_context(|| format!("read body failed: {}", d.url))?;
+        if let Some(chunk) = frame.data_ref() {
+            d.size += chunk.len() as u64;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::client::parse_resolve;
+    use crate::test_server::{Route, TestServer};
+
+    #[tokio::test]
+    async fn times_every_phase_of_a_plain_fetch() {
+        let srv = TestServer::start(HashMap::from([("/".to_string(), Route::ok("hello"))])).await;
+        let config = ClientConfig {
+            resolve: vec![parse_resolve("fixture.invalid=127.0.0.1").unwrap()],
+            ..ClientConfig::default()
+        };
+        let url = format!("http://fixture.invalid:{}/", srv.addr.port());
+        let d = diagnose(&config, &url, Duration::from_secs(5)).await;
+
+        assert!(d.error.is_none(), "{d}");
+        assert_eq!(d.remote, Some(srv.addr));
+        assert_eq!((d.status, d.size), (Some(200), 5));
+        assert_eq!(d.http_version.as_deref(), Some("HTTP/1.1"));
+        assert_eq!((d.appconnect, d.tls_version.as_deref()), (None, None));
+        let phases = [d.namelookup, d.connect, d.starttransfer, Some(d.total)];
+        assert!(phases.windows(2).all(|w| w[0] <= w[1]), "{d}");
+        assert!(d.to_string().contains("time_starttransfer:"));
+    }
+
+    #[tokio::test]
+    async fn a_refused_connect_stops_after_dns() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let url = format!("http://{addr}/");
+        let d = diagnose(&ClientConfig::default(), &url, Duration::from_secs(5)).await;
+        let err = format!("{:#}", d.error.unwrap());
+        assert!(err.starts_with("connect failed"), "{err}");
+        assert!(d.namelookup.is_some());
+        assert_eq!((d.connect, d.remote, d.status), (None, None, None));
+    }
+}
@@ -0,0 +1,539 @@
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use futures::StreamExt;
+use reqwest::Client;
+use reqwest::header::{ACCEPT_RANGES, CONTENT_LENGTH, ETAG, LAST_MODIFIED, RANGE};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::Mutex;
+use tokio::task::JoinSet;
+
+use crate::fetch::{self, StreamOptions};
+
+// Persist range progress after roughly this many new bytes per range
+const SAVE_EVERY: u64 = 1024 * 1024;
+
+pub struct DownloadOptions {
+    // How many ranges to fetch at once (when the server allows it)
+    pub parts: usize,
+    // Don't split into ranges smaller than this
+    pub min_part_size: u64,
+    // Expected SHA-256 (hex) of the finished file
+    pub sha256: Option<String>,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            parts: 4,
+            min_part_size: 1024 * 1024,
+            sha256: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DownloadReport {
+    pub bytes: u64,
+    pub ranges: usize,
+    // Some ranges were already (partly) on disk from an earlier run
+    pub resumed: bool,
+}
+
+// Sidecar `<dest>.part.json`: enough to tell whether a `.part` file still
+// belongs to the same remote resource, and how far each range got
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct Meta {
+    url: String,
+    size: u64,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    ranges: Vec<Span>,
+}
+
+// Inclusive byte range; `done` bytes from `start` are already on disk
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+struct Span {
+    start: u64,
+    end: u64,
+    done: u64,
+}
+
+impl Span {
+    fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    fn complete(&self) -> bool {
+        self.done >= self.len()
+    }
+}
+
+// What a HEAD request told us about the resource
+struct Probe {
+    size: Option<u64>,
+    ranges: bool,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+fn sidecar(dest: &Path, ext: &str) -> PathBuf {
+    let mut name = dest.as_os_str().to_owned();
+    name.push(ext);
+    PathBuf::from(name)
+}
+
+async fn probe(client: &Client, url: &str) -> Result<Probe> {
+    let resp = client
+        .head(url)
+        .send()
+        .await
+        .with_context(|| format!("request failed: HEAD {url}"))?;
+    let status = resp.status();
+    if !status.is_success() {
+        anyhow::bail!("{url} -> HTTP {status}");
+    }
+    let header = |name| {
+        resp.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    };
+    Ok(Probe {
+        // Not `resp.content_length()`: that is the (empty) HEAD body's size
+        size: header(CONTENT_LENGTH).and_then(|v| v.parse().ok()),
+        ranges: header(ACCEPT_RANGES).is_some_and(|v| v.eq_ignore_ascii_case("bytes")),
+        etag: header(ETAG),
+        last_modified: header(LAST_MODIFIED),
+    })
+}
+
+// Split `size` bytes into at most `parts` ranges of at least `min` bytes
+fn split(size: u64, parts: usize, min: u64) -> Vec<Span> {
+    let parts = (size / min.max(1)).clamp(1, parts.max(1) as u64);
+    let step = size.div_ceil(parts);
+    (0..parts)
+        .map(|i| i * step)
+        .take_while(|start| *start < size)
+        .map(|start| Span {
+            start,
+            end: (start + step).min(size) - 1,
+            done: 0,
+        })
+        .collect()
+}
+
+// Ranges save concurrently. The download's meta stays locked across the
+// write and rename, so saves of one download are serialized and the last
+// file written always has the latest progress; other downloads are not held
+// up. The temp file + rename keeps a crash from leaving half a sidecar behind.
+async fn save_meta(path: &Path, meta: &Mutex<Meta>) -> Result<()> {
+    let meta = meta.lock().await;
+    let json = serde_json::to_vec_pretty(&*meta)?;
+    let tmp = sidecar(path, ".tmp");
+    tokio::fs::write(&tmp, json)
+        .await
+        .with_context(|| format!("failed to write {}", tmp.display()))?;
+    tokio::fs::rename(&tmp, path)
+        .await
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+// Download `url` to `dest`. Data goes to `<dest>.part` with progress in
+// `<dest>.part.json`, so an interrupted run picks up where it stopped as long
+// as the server still reports the same size/ETag/Last-Modified.
+pub async fn download(
+    client: &Client,
+    url: &str,
+    dest: &Path,
+    opts: &DownloadOptions,
+) -> Result<DownloadReport> {
+    let part = sidecar(dest, ".part");
+    let meta_path = sidecar(dest, ".part.json");
+    let probe = probe(client, url).await?;
+
+    let report = match (probe.size, probe.ranges) {
+        (Some(size), true) if size > 0 => {
+            match download_ranges(client, url, &part, &meta_path, size, probe, opts).await {
+                // Advertised ranges but sent the whole body back anyway
+                Err(e) if e.is::<RangesIgnored>() => {
+                    download_whole(client, url, &part, &meta_path, Some(size)).await?
+                }
+                res => res?,
+            }
+        }
+        (size, _) => download_whole(client, url, &part, &meta_path, size).await?,
+    };
+
+    if let Some(expected) = &opts.sha256 {
+        let actual = sha256_file(&part).await?;
+        if !actual.eq_ignore_ascii_case(expected) {
+            // The data is bad, so resuming from it would only repeat the error
+            let _ = tokio::fs::remove_file(&part).await;
+            let _ = tokio::fs::remove_file(&meta_path).await;
+            anyhow::bail!("{url} -> sha256 mismatch: expected {expected}, got {actual}");
+        }
+    }
+
+    tokio::fs::rename(&part, dest)
+        .await
+        .with_context(|| format!("failed to move download to {}", dest.display()))?;
+    let _ = tokio::fs::remove_file(&meta_path).await;
+    Ok(report)
+}
+
+// No ranges (or no size): one plain stream, nothing to resume
+async fn download_whole(
+    client: &Client,
+    url: &str,
+    part: &Path,
+    meta_path: &Path,
+    size: Option<u64>,
+) -> Result<DownloadReport> {
+    let _ = tokio::fs::remove_file(meta_path).await;
+    let stream_opts = StreamOptions {
+        save_to: Some(part.to_path_buf()),
+        ..StreamOptions::default()
+    };
+    let streamed = fetch::fetch_stream(client, url, &stream_opts).await?;
+    if let Some(size) = size
+        && streamed.len != size
+    {
+        anyhow::bail!("{url} -> got {} bytes, expected {size}", streamed.len);
+    }
+    Ok(DownloadReport {
+        bytes: streamed.len,
+        ranges: 1,
+        resumed: false,
+    })
+}
+
+// A range request came back `200 OK` with the whole body
+#[derive(Debug)]
+struct RangesIgnored;
+
+impl std::fmt::Display for RangesIgnored {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("server ignored the Range header")
+    }
+}
+
+impl std::error::Error for RangesIgnored {}
+
+async fn download_ranges(
+    client: &Client,
+    url: &str,
+    part: &Path,
+    meta_path: &Path,
+    size: u64,
+    probe: Probe,
+    opts: &DownloadOptions,
+) -> Result<DownloadReport> {
+    let fresh = Meta {
+        url: url.to_string(),
+        size,
+        etag: probe.etag,
+        last_modified: probe.last_modified,
+        ranges: split(size, opts.parts, opts.min_part_size),
+    };
+
+    // Resume only if both files are there and describe the same resource
+    let previous = match tokio::fs::read(meta_path).await {
+        Ok(json) if tokio::fs::try_exists(part).await.unwrap_or(false) => {
+            serde_json::from_slice::<Meta>(&json).ok()
+        }
+        _ => None,
+    };
+    let (meta, resumed) = match previous {
+        Some(m)
+            if m.url == fresh.url
+                && m.size == fresh.size
+                && m.etag == fresh.etag
+                && m.last_modified == fresh.last_modified =>
+        {
+            let resumed = m.ranges.iter().any(|s| s.done > 0);
+            (m, resumed)
+        }
+        _ => (fresh, false),
+    };
+
+    let file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(!resumed)
+        .open(part)
+        .await
+        .with_context(|| format!("failed to open {}", part.display()))?;
+    file.set_len(size).await?;
+    drop(file);
+
+    let count = meta.ranges.len();
+    let meta = Arc::new(Mutex::new(meta));
+    save_meta(meta_path, &meta).await?;
+
+    let mut tasks = JoinSet::new();
+    for i in 0..count {
+        if meta.lock().await.ranges[i].complete() {
+            continue;
+        }
+        tasks.spawn(fetch_range(
+            client.clone(),
+            url.to_string(),
+            part.to_path_buf(),
+            meta_path.to_path_buf(),
+            meta.clone(),
+            i,
+        ));
+    }
+    // Let every range run to the end (or to its own error) so each one
+    // records its progress; then report the first failure
+    let mut first_err = None;
+    while let Some(res) = tasks.join_next().await {
+        let res = res.context("range task panicked").flatten();
+        if let Err(e) = res {
+            first_err.get_or_insert(e);
+        }
+    }
+    if let Some(e) = first_err {
+        return Err(e);
+    }
+
+    let written: u64 = meta.lock().await.ranges.iter().map(|s| s.done).sum();
+    if written != size {
+        anyhow::bail!("{url} -> got {written} bytes, expected {size}");
+    }
+    Ok(DownloadReport {
+        bytes: written,
+        ranges: count,
+        resumed,
+    })
+}
+
+async fn fetch_range(
+    client: Client,
+    url: String,
+    part: PathBuf,
+    meta_path: PathBuf,
+    meta: Arc<Mutex<Meta>>,
+    i: usize,
+) -> Result<()> {
+    let span = meta.lock().await.ranges[i];
+    let from = span.start + span.done;
+    let resp = client
+        .get(&url)
+        .header(RANGE, format!("bytes={from}-{}", span.end))
+        .send()
+        .await
+        .with_context(|| format!("request failed: GET {url} (bytes {from}-{})", span.end))?;
+    if resp.status() == reqwest::StatusCode::OK {
+        return Err(RangesIgnored.into());
+    }
+    if resp.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+        anyhow::bail!("{url} -> HTTP {} for a range request", resp.status());
+    }
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(&part)
+        .await?;
+    file.seek(SeekFrom::Start(from)).await?;
+
+    // `done` in the shared meta only moves after a flush, so a sidecar saved
+    // by any range never claims bytes that are still in flight
+    let mut done = span.done;
+    let mut unsaved = 0;
+    let mut body = resp.bytes_stream();
+    let streamed: Result<()> = async {
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.with_context(|| format!("read body failed: {url}"))?;
+            if chunk.len() as u64 > span.len() - done {
+                anyhow::bail!("{url} -> server sent more than the requested range");
+            }
+            file.write_all(&chunk).await?;
+            done += chunk.len() as u64;
+            unsaved += chunk.len() as u64;
+            if unsaved >= SAVE_EVERY {
+                file.flush().await?;
+                meta.lock().await.ranges[i].done = done;
+                save_meta(&meta_path, &meta).await?;
+                unsaved = 0;
+            }
+        }
+        Ok(())
+    }
+    .await;
+
+    // Keep what did arrive, even when the stream broke off halfway
+    file.flush().await?;
+    meta.lock().await.ranges[i].done = done;
+    save_meta(&meta_path, &meta).await?;
+    streamed
+}
+
+async fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::test_server::{Route, TestServer};
+
+    fn body(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    fn opts() -> DownloadOptions {
+        DownloadOptions {
+            parts: 4,
+            min_part_size: 1000,
+            sha256: None,
+        }
+    }
+
+    fn ranged(data: &[u8]) -> Route {
+        Route {
+            ranges: true,
+            ..Route::ok(data.to_vec())
+        }
+    }
+
+    #[tokio::test]
+    async fn splits_into_ranges_and_verifies_checksum() {
+        let data = body(10_000);
+        let srv = TestServer::start(HashMap::from([("/f".to_string(), ranged(&data))])).await;
+        let dir = tempdir("ranges");
+        let dest = dir.join("f.bin");
+
+        let expected = Sha256::digest(&data)
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        let opts = DownloadOptions {
+            sha256: Some(expected),
+            ..opts()
+        };
+        let report = download(&Client::new(), &srv.url("/f"), &dest, &opts)
+            .await
+            .unwrap();
+
+        assert_eq!(report.ranges, 4);
+        assert!(!report.resumed);
+        assert_eq!(std::fs::read(&dest).unwrap(), data);
+        assert!(!sidecar(&dest, ".part.json").exists());
+        let gets = srv
+            .requests()
+            .iter()
+            .filter(|r| r.contains("range="))
+            .count();
+        assert_eq!(gets, 4);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn resumes_after_truncated_ranges() {
+        let data = body(10_000);
+        let srv = TestServer::start(HashMap::from([(
+            "/f".to_string(),
+            Route {
+                cut_after: Some(1000),
+                ..ranged(&data)
+            },
+        )]))
+        .await;
+        let dir = tempdir("resume");
+        let dest = dir.join("f.bin");
+        let client = Client::new();
+
+        assert!(
+            download(&client, &srv.url("/f"), &dest, &opts())
+                .await
+                .is_err()
+        );
+        assert!(sidecar(&dest, ".part.json").exists());
+
+        srv.set_route("/f", ranged(&data));
+        let report = download(&client, &srv.url("/f"), &dest, &opts())
+            .await
+            .unwrap();
+        assert!(report.resumed);
+        assert_eq!(std::fs::read(&dest).unwrap(), data);
+        // Second run picked up 1000 bytes into the first range
+        assert!(
+            srv.requests()
+                .iter()
+                .any(|r| r.ends_with("range=bytes=1000-2499"))
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_one_stream_when_ranges_are_ignored() {
+        let data = body(10_000);
+        // Says it takes ranges, then answers every GET with the whole body
+        let route = Route {
+            headers: vec![("Accept-Ranges".into(), "bytes".into())],
+            ..Route::ok(data.clone())
+        };
+        let srv = TestServer::start(HashMap::from([("/f".to_string(), route)])).await;
+        let dir = tempdir("ignored");
+        let dest = dir.join("f.bin");
+
+        let report = download(&Client::new(), &srv.url("/f"), &dest, &opts())
+            .await
+            .unwrap();
+        assert_eq!(report.ranges, 1);
+        assert_eq!(std::fs::read(&dest).unwrap(), data);
+        assert!(!sidecar(&dest, ".part.json").exists());
+        assert_eq!(srv.requests().last().unwrap(), "GET /f");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn checksum_mismatch_discards_partial_data() {
+        let srv = TestServer::start(HashMap::from([("/f".to_string(), ranged(&body(3000)))])).await;
+        let dir = tempdir("badsum");
+        let dest = dir.join("f.bin");
+        let opts = DownloadOptions {
+            sha256: Some("00".repeat(32)),
+            ..opts()
+        };
+
+        let err = download(&Client::new(), &srv.url("/f"), &dest, &opts)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("sha256 mismatch"));
+        assert!(!dest.exists());
+        assert!(!sidecar(&dest, ".part").exists());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    fn tempdir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("lesson05-dl-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+}
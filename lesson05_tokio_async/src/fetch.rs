@@ -0,0 +1,412 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use futures::StreamExt;
+use reqwest::Client;
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::watch;
+use tokio::time::timeout;
+
+use crate::retry::FetchError;
+
+// What to do with the body while it streams past
+#[derive(Default)]
+pub struct StreamOptions {
+    // Fail once more than this many bytes arrive (checked against
+    // Content-Length up front, and again while streaming)
+    pub max_bytes: Option<u64>,
+    // Compute a SHA-256 of the body
+    pub sha256: bool,
+    // Fail unless the body's SHA-256 is this (hex); implies `sha256`
+    pub expect_sha256: Option<String>,
+    // Also write the body to this file (removed again on failure)
+    pub save_to: Option<PathBuf>,
+    // Receives a new `Progress` after every chunk
+    pub progress: Option<watch::Sender<Progress>>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Progress {
+    pub received: u64,
+    // From Content-Length, when the server sent one
+    pub total: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct Streamed {
+    pub len: u64,
+    // Lowercase hex, only when `StreamOptions::sha256` was set
+    pub sha256: Option<String>,
+}
+
+// Stream the body chunk by chunk instead of buffering it: only the running
+// count (and optionally a hash / the output file) is kept
+pub async fn fetch_stream(client: &Client, url: &str, opts: &StreamOptions) -> Result<Streamed> {
+    // GET request
+    let resp = client
+        .get(url)
+        .send()
+        .await
+        .with_context(|| format!("request failed: GET {url}"))?;
+
+    // Treat non-2xx as an error, before reading any of the body
+    let status = resp.status();
+    if !status.is_success() {
+        anyhow::bail!("{url} -> HTTP {status}");
+    }
+
+    let total = resp.content_length();
+    if let (Some(max), Some(total)) = (opts.max_bytes, total)
+        && total > max
+    {
+        anyhow::bail!("{url} -> body is {total} bytes, limit is {max}");
+    }
+
+    let mut file = match &opts.save_to {
+        Some(path) => Some(
+            tokio::fs::File::create(path)
+                .await
+                .with_context(|| format!("failed to create {}", path.display()))?,
+        ),
+        None => None,
+    };
+
+    let res = stream_body(resp, url, total, opts, file.as_mut()).await;
+    if let Some(path) = &opts.save_to {
+        match &res {
+            Ok(_) => {
+                if let Some(f) = file.as_mut() {
+                    f.flush().await?;
+                }
+            }
+            Err(_) => {
+                drop(file);
+                let _ = tokio::fs::remove_file(path).await;
+            }
+        }
+    }
+    res
+}
+
+async fn stream_body(
+    resp: reqwest::Response,
+    url: &str,
+    total: Option<u64>,
+    opts: &StreamOptions,
+    mut file: Option<&mut tokio::fs::File>,
+) -> Result<Streamed> {
+    let mut hasher = (opts.sha256 || opts.expect_sha256.is_some()).then(Sha256::new);
+    let mut received: u64 = 0;
+    let mut body = resp.bytes_stream();
+
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.with_context(|| format!("read body failed: {url}"))?;
+        received += chunk.len() as u64;
+        if let Some(max) = opts.max_bytes
+            && received > max
+        {
+            anyhow::bail!("{url} -> body exceeds limit of {max} bytes");
+        }
+        if let Some(h) = hasher.as_mut() {
+            h.update(&chunk);
+        }
+        if let Some(f) = file.as_deref_mut() {
+            f.write_all(&chunk)
+                .await
+                .with_context(|| format!("write failed while saving {url}"))?;
+        }
+        if let Some(tx) = &opts.progress {
+            tx.send_replace(Progress { received, total });
+        }
+    }
+
+    let sha256: Option<String> =
+        hasher.map(|h| h.finalize().iter().map(|b| format!("{b:02x}")).collect());
+    if let (Some(expected), Some(actual)) = (&opts.expect_sha256, &sha256)
+        && !expected.eq_ignore_ascii_case(actual)
+    {
+        anyhow::bail!("{url} -> sha256 mismatch: expected {expected}, got {actual}");
+    }
+    Ok(Streamed {
+        len: received,
+        sha256,
+    })
+}
+
+// Return the body length in bytes without holding the body in memory
+pub async fn fetch_len(client: &Client, url: &str) -> Result<usize> {
+    let streamed = fetch_stream(client, url, &StreamOptions::default()).await?;
+    Ok(streamed.len as usize)
+}
+
+// Wrap a single fetch with a timeout and return (url, result).
+pub async fn fetch_task(client: &Client, url: &str) -> (String, Result<usize>) {
+    let fut = fetch_len(client, url);
+    let res = timeout(Duration::from_secs(10), fut)
+        .await
+        .map_err(|_| anyhow::Error::new(FetchError::Timeout))
+        .flatten();
+
+    (url.to_string(), res)
+}
+
+// The whole body as text, with the same status check, timeout and error
+// context as `fetch_task`; for pages that get parsed afterwards
+pub async fn fetch_text(client: &Client, url: &str) -> Result<String> {
+    let fut = async {
+        let resp = client
+            .get(url)
+            .send()
+            .await
+            .with_context(|| format!("request failed: GET {url}"))?;
+        let status = resp.status();
+        if !status.is_success() {
+            anyhow::bail!("{url} -> HTTP {status}");
+        }
+        resp.text()
+            .await
+            .with_context(|| format!("read body failed: {url}"))
+    };
+    timeout(Duration::from_secs(10), fut)
+        .await
+        .map_err(|_| anyhow::Error::new(FetchError::Timeout))
+        .flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use tokio::time::Instant;
+
+    use super::*;
+    use crate::test_server::{Route, TestServer};
+
+    async fn serve(route: Route) -> TestServer {
+        TestServer::start(HashMap::from([("/".to_string(), route)])).await
+    }
+
+    #[tokio::test]
+    async fn fetch_len_counts_the_body() {
+        let srv = serve(Route::ok(vec![b'x'; 70_000])).await;
+        assert_eq!(
+            fetch_len(&Client::new(), &srv.url("/")).await.unwrap(),
+            70_000
+        );
+    }
+
+    #[tokio::test]
+    async fn fetch_len_rejects_non_2xx() {
+        let srv = serve(Route {
+            status: 503,
+            ..Route::ok("busy")
+        })
+        .await;
+        let err = fetch_len(&Client::new(), &srv.url("/")).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("{} -> HTTP 503 Service Unavailable", srv.url("/"))
+        );
+    }
+
+    #[tokio::test]
+    async fn fetch_len_waits_out_a_slow_body() {
+        let srv = serve(Route {
+            stall: Some(Duration::from_millis(300)),
+            ..Route::ok("late")
+        })
+        .await;
+        let start = Instant::now();
+        assert_eq!(fetch_len(&Client::new(), &srv.url("/")).await.unwrap(), 4);
+        assert!(start.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[tokio::test]
+    async fn fetch_len_reports_a_connection_reset() {
+        let srv = serve(Route {
+            cut_after: Some(100),
+            reset: true,
+            ..Route::ok(vec![b'x'; 10_000])
+        })
+        .await;
+        let err = fetch_len(&Client::new(), &srv.url("/")).await.unwrap_err();
+        assert!(format!("{err:#}").starts_with(&format!("read body failed: {}", srv.url("/"))));
+    }
+
+    // With the clock paused, tokio jumps ahead whenever every task is idle,
+    // so the 10s timeout fires right away and at exactly 10s of tokio time
+    #[tokio::test(start_paused = true)]
+    async fn fetch_task_times_out_after_10s() {
+        let srv = serve(Route {
+            stall: Some(Duration::from_secs(60)),
+            ..Route::ok("never")
+        })
+        .await;
+        let start = Instant::now();
+        let (url, res) = fetch_task(&Client::new(), &srv.url("/")).await;
+        assert_eq!(url, srv.url("/"));
+        let err = res.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FetchError::Timeout)));
+        assert_eq!(start.elapsed(), Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn max_bytes_checks_content_length_up_front() {
+        let srv = serve(Route::ok(vec![b'x'; 100])).await;
+        let opts = StreamOptions {
+            max_bytes: Some(50),
+            ..StreamOptions::default()
+        };
+        let err = fetch_stream(&Client::new(), &srv.url("/"), &opts)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("{} -> body is 100 bytes, limit is 50", srv.url("/"))
+        );
+    }
+
+    #[tokio::test]
+    async fn max_bytes_stops_an_unannounced_body() {
+        let srv = serve(Route {
+            chunked: true,
+            ..Route::ok(vec![b'x'; 100])
+        })
+        .await;
+        let opts = StreamOptions {
+            max_bytes: Some(50),
+            ..StreamOptions::default()
+        };
+        let err = fetch_stream(&Client::new(), &srv.url("/"), &opts)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("{} -> body exceeds limit of 50 bytes", srv.url("/"))
+        );
+
+        let opts = StreamOptions {
+            max_bytes: Some(100),
+            ..StreamOptions::default()
+        };
+        let streamed = fetch_stream(&Client::new(), &srv.url("/"), &opts)
+            .await
+            .unwrap();
+        assert_eq!(streamed.len, 100);
+    }
+
+    #[tokio::test]
+    async fn sha256_is_computed_and_verified() {
+        let srv = serve(Route::ok("abc")).await;
+        let abc = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        let opts = StreamOptions {
+            sha256: true,
+            ..StreamOptions::default()
+        };
+        let streamed = fetch_stream(&Client::new(), &srv.url("/"), &opts)
+            .await
+            .unwrap();
+        assert_eq!(streamed.sha256.as_deref(), Some(abc));
+
+        let opts = StreamOptions {
+            expect_sha256: Some(abc.to_uppercase()),
+            ..StreamOptions::default()
+        };
+        assert!(
+            fetch_stream(&Client::new(), &srv.url("/"), &opts)
+                .await
+                .is_ok()
+        );
+
+        let wrong = "0".repeat(64);
+        let opts = StreamOptions {
+            expect_sha256: Some(wrong.clone()),
+            ..StreamOptions::default()
+        };
+        let err = fetch_stream(&Client::new(), &srv.url("/"), &opts)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "{} -> sha256 mismatch: expected {wrong}, got {abc}",
+                srv.url("/")
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn save_to_is_removed_on_failure() {
+        let dir = std::env::temp_dir().join(format!("lesson05-fetch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("body");
+        let srv = serve(Route::ok("hello")).await;
+        let opts = StreamOptions {
+            save_to: Some(path.clone()),
+            ..StreamOptions::default()
+        };
+        fetch_stream(&Client::new(), &srv.url("/"), &opts)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello");
+
+        // A body cut short by a reset, then one failing verification
+        srv.set_route(
+            "/",
+            Route {
+                cut_after: Some(100),
+                reset: true,
+                ..Route::ok(vec![b'x'; 10_000])
+            },
+        );
+        assert!(
+            fetch_stream(&Client::new(), &srv.url("/"), &opts)
+                .await
+                .is_err()
+        );
+        assert!(!path.exists());
+
+        srv.set_route("/", Route::ok("hello"));
+        let opts = StreamOptions {
+            expect_sha256: Some("0".repeat(64)),
+            ..opts
+        };
+        assert!(
+            fetch_stream(&Client::new(), &srv.url("/"), &opts)
+                .await
+                .is_err()
+        );
+        assert!(!path.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn progress_reports_every_chunk() {
+        let srv = serve(Route::ok(vec![b'x'; 70_000])).await;
+        let (tx, mut rx) = watch::channel(Progress::default());
+        let seen = tokio::spawn(async move {
+            let mut seen = Vec::new();
+            while rx.changed().await.is_ok() {
+                seen.push(*rx.borrow_and_update());
+            }
+            seen
+        });
+        let opts = StreamOptions {
+            progress: Some(tx),
+            ..StreamOptions::default()
+        };
+        let streamed = fetch_stream(&Client::new(), &srv.url("/"), &opts)
+            .await
+            .unwrap();
+        // Dropping the sender ends the watcher
+        drop(opts);
+        let seen = seen.await.unwrap();
+
+        assert_eq!(streamed.len, 70_000);
+        let last = seen.last().unwrap();
+        assert_eq!((last.received, last.total), (70_000, Some(70_000)));
+        assert!(seen.windows(2).all(|w| w[0].received < w[1].received));
+    }
+}
@@ -0,0 +1,120 @@
+// Log-linear latency histogram in the style of HdrHistogram: values below
+// 128 get their own bucket, every power of two above that is split into 64
+// buckets. Any recorded value is off by at most 1/64 (~1.6%), and the whole
+// u64 range fits in under 4k counters.
+
+const SUB_BITS: u32 = 6;
+const SUB: u64 = 1 << SUB_BITS;
+const BUCKETS: usize = (63 - SUB_BITS as usize) * SUB as usize + 2 * SUB as usize;
+
+#[derive(Clone, Debug)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    total: u64,
+    sum: u128,
+    min: u64,
+    max: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            counts: vec![0; BUCKETS],
+            total: 0,
+            sum: 0,
+            min: u64::MAX,
+            max: 0,
+        }
+    }
+}
+
+fn index(v: u64) -> usize {
+    if v < 2 * SUB {
+        return v as usize;
+    }
+    let shift = 63 - v.leading_zeros() - SUB_BITS;
+    (shift as u64 * SUB + (v >> shift)) as usize
+}
+
+// Largest value that lands in bucket `i`
+fn upper_bound(i: usize) -> u64 {
+    let i = i as u64;
+    if i < 2 * SUB {
+        return i;
+    }
+    let shift = i / SUB - 1;
+    let m = i % SUB + SUB;
+    // Wraps for the very last bucket, whose top is u64::MAX
+    ((m + 1) << shift).wrapping_sub(1)
+}
+
+impl Histogram {
+    pub fn record(&mut self, v: u64) {
+        self.counts[index(v)] += 1;
+        self.total += 1;
+        self.sum += v as u128;
+        self.min = self.min.min(v);
+        self.max = self.max.max(v);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.total
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        self.sum as f64 / self.total as f64
+    }
+
+    // Nearest-rank percentile, reported as the top of its bucket (never
+    // above the largest value actually seen); 0 when empty
+    pub fn percentile(&self, p: f64) -> u64 {
+        if self.total == 0 {
+            return 0;
+        }
+        let rank = ((p / 100.0) * self.total as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, n) in self.counts.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return upper_bound(i).clamp(self.min, self.max);
+            }
+        }
+        self.max
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_are_contiguous_and_precise() {
+        for v in [0, 1, 127, 128, 129, 255, 256, 1_000, 123_456_789, u64::MAX] {
+            let i = index(v);
+            assert!(v <= upper_bound(i), "{v} above its bucket");
+            assert!(i == 0 || upper_bound(i - 1) < v, "{v} below its bucket");
+            assert!((upper_bound(i) - v) as f64 <= v as f64 / SUB as f64);
+        }
+        assert_eq!(index(u64::MAX), BUCKETS - 1);
+    }
+
+    #[test]
+    fn percentiles_stay_within_a_bucket() {
+        let mut h = Histogram::default();
+        for v in 1..=10_000 {
+            h.record(v);
+        }
+        let near = |got: u64, want: u64| got >= want && got - want <= want / 64;
+        assert!(near(h.percentile(50.0), 5_000));
+        assert!(near(h.percentile(99.0), 9_900));
+        assert_eq!(h.percentile(100.0), 10_000);
+        assert_eq!((h.count(), h.max()), (10_000, 10_000));
+    }
+}
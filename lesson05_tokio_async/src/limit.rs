@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use reqwest::Url;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::{Instant, sleep_until};
+
+// Longest gap between two starts a rate may ask for; anything slower than
+// one request a day is a typo, and a longer period could overflow Instant
+const MAX_PERIOD: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HostLimits {
+    // Requests started per second, per host
+    pub per_second: Option<f64>,
+    // Requests in flight at once, per host
+    pub max_concurrent: Option<usize>,
+}
+
+struct HostState {
+    slots: Option<Arc<Semaphore>>,
+    // When the next request to this host may start
+    next: Mutex<Instant>,
+}
+
+// Per-host rate limiter shared by all fetches; cheap to clone. Hosts are
+// keyed by scheme, host and port, so http and https count separately.
+#[derive(Clone)]
+pub struct HostLimiter {
+    limits: HostLimits,
+    hosts: Arc<Mutex<HashMap<String, Arc<HostState>>>>,
+}
+
+// Holds one of the host's concurrency slots until dropped
+pub struct Permit {
+    _slot: Option<OwnedSemaphorePermit>,
+}
+
+impl HostLimiter {
+    pub fn new(limits: HostLimits) -> Self {
+        Self {
+            limits,
+            hosts: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // Wait for a concurrency slot, then for the host's next rate slot
+    pub async fn acquire(&self, url: &str) -> Permit {
+        let state = {
+            let mut hosts = self.hosts.lock().unwrap();
+            hosts
+                .entry(Url::parse(url).map_or_else(|_| String::new(), |u| host_key(&u)))
+                .or_insert_with(|| {
+                    Arc::new(HostState {
+                        slots: self
+                            .limits
+                            .max_concurrent
+                            .map(|n| Arc::new(Semaphore::new(n.max(1)))),
+                        next: Mutex::new(Instant::now()),
+                    })
+                })
+                .clone()
+        };
+
+        let slot = match &state.slots {
+            Some(sem) => Some(
+                sem.clone()
+                    .acquire_owned()
+                    .await
+                    .expect("semaphore is never closed"),
+            ),
+            None => None,
+        };
+
+        if let Some(every) = self.limits.per_second.and_then(period) {
+            let at = {
+                let mut next = state.next.lock().unwrap();
+                let at = (*next).max(Instant::now());
+                *next = at + every;
+                at
+            };
+            sleep_until(at).await;
+        }
+        Permit { _slot: slot }
+    }
+}
+
+// Gap between starts at `rate` per second, clamped to 1ns..=MAX_PERIOD;
+// None (no limit) for zero, negative or NaN rates
+pub fn period(rate: f64) -> Option<Duration> {
+    if rate.is_nan() || rate <= 0.0 {
+        return None;
+    }
+    let secs = (1.0 / rate).min(MAX_PERIOD.as_secs_f64());
+    Some(Duration::from_secs_f64(secs).max(Duration::from_nanos(1)))
+}
+
+// `--rate`: requests per second whose period needs no clamping
+pub fn parse_rate(s: &str) -> Result<f64, String> {
+    let rate: f64 = s.parse().map_err(|e| format!("{e}"))?;
+    let min = 1.0 / MAX_PERIOD.as_secs_f64();
+    if !(min..=1e9).contains(&rate) {
+        return Err(format!(
+            "must be between {min:.6} (one a day) and 1e9 requests per second"
+        ));
+    }
+    Ok(rate)
+}
+
+// Scheme, host and port (the URL's origin): what the limiter and the crawler
+// count per host, and where the paginator may send credentials.
+// (`acquire` puts unparsable URLs in one bucket; the fetch reports the error.)
+pub fn host_key(url: &Url) -> String {
+    format!(
+        "{}://{}:{}",
+        url.scheme(),
+        url.host_str().unwrap_or_default(),
+        url.port_or_known_default().unwrap_or_default()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn spaces_requests_per_host() {
+        let limiter = HostLimiter::new(HostLimits {
+            per_second: Some(10.0),
+            max_concurrent: None,
+        });
+        let start = Instant::now();
+        for _ in 0..5 {
+            limiter.acquire("http://a.test/x").await;
+        }
+        assert_eq!(start.elapsed(), Duration::from_millis(400));
+
+        // Another host has its own budget
+        let start = Instant::now();
+        limiter.acquire("http://b.test/").await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+
+    #[test]
+    fn rates_stay_in_range() {
+        assert_eq!(period(4.0), Some(Duration::from_millis(250)));
+        assert_eq!(period(1e-300), Some(MAX_PERIOD));
+        assert_eq!(period(f64::INFINITY), Some(Duration::from_nanos(1)));
+        assert_eq!(period(0.0), None);
+        assert_eq!(period(f64::NAN), None);
+
+        assert_eq!(parse_rate("2.5"), Ok(2.5));
+        for bad in ["0", "-1", "1e-300", "inf", "NaN", "2e9", "fast"] {
+            assert!(parse_rate(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn caps_concurrency_per_host() {
+        let limiter = HostLimiter::new(HostLimits {
+            per_second: None,
+            max_concurrent: Some(2),
+        });
+        let held = [
+            limiter.acquire("http://a.test/1").await,
+            limiter.acquire("http://a.test/2").await,
+        ];
+        let third =
+            tokio::time::timeout(Duration::from_secs(1), limiter.acquire("http://a.test/3"));
+        assert!(third.await.is_err());
+        assert!(limiter.acquire("http://b.test/").await._slot.is_some());
+
+        drop(held);
+        limiter.acquire("http://a.test/3").await;
+    }
+}
@@ -1,95 +1,933 @@
+mod api;
+mod batch;
+mod bench;
+mod cache;
+mod client;
+mod crawl;
+mod diag;
+mod download;
+mod fetch;
+mod histogram;
+mod limit;
+mod monitor;
+mod pipeline;
+mod retry;
+mod robots;
+mod supervisor;
+#[cfg(test)]
+mod test_server;
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use futures::StreamExt;
 use reqwest::Client;
-use tokio::time::{sleep, timeout};
+use tokio::io::AsyncReadExt;
+use tokio::sync::watch;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use api::{Auth, PageOptions, Paging};
+use batch::{BatchOptions, FetchMany, Order, fetch_many};
+use bench::{BenchOptions, Limit, Model};
+use cache::Cache;
+use client::{ClientConfig, HttpVersion};
+use crawl::CrawlOptions;
+use download::DownloadOptions;
+use fetch::{Progress, StreamOptions};
+use limit::{HostLimiter, HostLimits};
+use monitor::{AlertSink, MonitorOptions, Probe, Target};
+use pipeline::{Pipeline, StageOptions};
+use retry::RetryPolicy;
+use supervisor::{Exit, Restart, Supervisor};
+
+#[derive(Parser)]
+#[command(name = "lesson05_tokio_async", about = "Tokio async basics + reqwest")]
+struct Cli {
+    // No subcommand = the original sleep/worker/fetch demo
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    // Idle connections kept per host
+    #[arg(long, global = true, value_name = "N", default_value_t = 32)]
+    pool_max_idle: usize,
+
+    // Close idle pooled connections after this many seconds
+    #[arg(long, global = true, value_name = "SECS", default_value_t = 15)]
+    pool_idle_timeout: u64,
+
+    // HTTP version preference
+    #[arg(long, global = true, value_enum, default_value_t)]
+    http: HttpVersion,
+
+    // User-Agent header (also used to pick the robots.txt group)
+    #[arg(long, global = true, default_value = client::DEFAULT_USER_AGENT)]
+    user_agent: String,
+
+    // Send requests through this proxy URL
+    #[arg(long, global = true, value_name = "URL")]
+    proxy: Option<String>,
+
+    // Resolve HOST to IP instead of using DNS (repeatable)
+    #[arg(long, global = true, value_name = "HOST=IP", value_parser = client::parse_resolve)]
+    resolve: Vec<(String, std::net::IpAddr)>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    // Fetch many URLs with bounded concurrency and print a summary
+    Fetch {
+        // URLs to fetch (in addition to --file)
+        urls: Vec<String>,
+
+        // Read URLs from a file, one per line (`-` = stdin)
+        #[arg(short, long, value_name = "PATH")]
+        file: Option<PathBuf>,
+
+        // Max requests in flight
+        #[arg(short, long, default_value_t = 8)]
+        concurrency: usize,
+
+        // Print results in input order instead of as they finish
+        #[arg(long)]
+        input_order: bool,
+
+        // Retry transient failures (connect, timeout, 5xx, 429) up to N times
+        #[arg(long, value_name = "N", default_value_t = 0)]
+        retries: u32,
+
+        // Time budget per URL across all retries, in seconds
+        #[arg(long, value_name = "SECS", default_value_t = 30)]
+        budget: u64,
+
+        // Start at most this many requests per second to any one host
+        #[arg(long, value_name = "PER_SEC", value_parser = limit::parse_rate)]
+        rate: Option<f64>,
+
+        // Keep at most this many requests in flight to any one host
+        #[arg(long, value_name = "N")]
+        max_per_host: Option<usize>,
+
+        // Cache bodies here and revalidate them with conditional requests
+        // (ETag / Last-Modified) on later runs; replaces --retries
+        #[arg(long, value_name = "DIR", conflicts_with = "retries")]
+        cache_dir: Option<PathBuf>,
+
+        // Serve only from --cache-dir, without touching the network
+        #[arg(long, requires = "cache_dir")]
+        offline: bool,
+
+        // Time DNS, connect, TLS, first byte and body for each URL on a
+        // fresh connection and print a curl -w style breakdown instead
+        #[arg(long, conflicts_with_all = ["retries", "cache_dir"])]
+        diag: bool,
+    },
+
+    // Stream one URL: count bytes, optionally hash and/or save it
+    Get {
+        url: String,
+
+        // Write the body to this file
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+
+        // Print the SHA-256 of the body
+        #[arg(long)]
+        sha256: bool,
+
+        // Fail (and remove `--output`) unless the body has this SHA-256 (hex)
+        #[arg(long, value_name = "HEX")]
+        expect_sha256: Option<String>,
+
+        // Give up once the body grows past this many bytes
+        #[arg(long, value_name = "BYTES")]
+        max_bytes: Option<u64>,
+    },
+
+    // Save a URL to a file, in parallel ranges when the server supports it;
+    // re-running the same command resumes an interrupted download
+    Download {
+        url: String,
+
+        // Destination file
+        #[arg(short, long, value_name = "PATH")]
+        output: PathBuf,
+
+        // Ranges fetched concurrently
+        #[arg(short, long, default_value_t = 4)]
+        parts: usize,
+
+        // Expected SHA-256 (hex) of the finished file
+        #[arg(long, value_name = "HEX")]
+        sha256: Option<String>,
+    },
+
+    // Crawl from seed URLs, writing one JSON line per page
+    Crawl {
+        seeds: Vec<String>,
+
+        // Follow links this many hops from a seed
+        #[arg(short, long, default_value_t = 2)]
+        depth: usize,
+
+        // Stop after fetching this many pages
+        #[arg(long, default_value_t = 100)]
+        max_pages: usize,
+
+        // Only visit these domains and their subdomains (repeatable);
+        // defaults to the seeds' hosts
+        #[arg(long = "allow-domain", value_name = "DOMAIN")]
+        allow_domains: Vec<String>,
+
+        // Wait at least this long between requests to one host
+        #[arg(long, value_name = "MS", default_value_t = 500)]
+        delay_ms: u64,
+
+        // Pages in flight across all hosts
+        #[arg(short, long, default_value_t = 4)]
+        concurrency: usize,
+
+        // Don't fetch or obey robots.txt
+        #[arg(long)]
+        ignore_robots: bool,
+
+        // Write the JSONL report here instead of stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+
+    // Probe endpoints on a schedule and alert when they go down or recover
+    Monitor {
+        // URLs to probe with the checks below (in addition to --config)
+        urls: Vec<String>,
+
+        // JSON list of targets: {"url", "name", "expect_status",
+        // "body_contains", "body_regex", "max_latency_ms", "interval_ms"}
+        #[arg(short, long, value_name = "PATH")]
+        config: Option<PathBuf>,
+
+        // Seconds between probes of each target
+        #[arg(
+            short,
+            long,
+            value_name = "SECS",
+            default_value_t = 30,
+            value_parser = clap::value_parser!(u64).range(1..)
+        )]
+        interval: u64,
+
+        // Timeout in seconds for each probe and each webhook alert
+        #[arg(long, value_name = "SECS", default_value_t = 10)]
+        timeout: u64,
+
+        #[arg(long, value_name = "CODE", default_value_t = 200)]
+        expect_status: u16,
+
+        // The body must contain this text
+        #[arg(long, value_name = "TEXT")]
+        contains: Option<String>,
+
+        // The body must match this regex
+        #[arg(long, value_name = "REGEX")]
+        regex: Option<String>,
+
+        // Slower responses count as failures
+        #[arg(long, value_name = "MS")]
+        max_latency_ms: Option<u64>,
+
+        // Failures in a row before a target is reported down
+        #[arg(long, value_name = "N", default_value_t = 2)]
+        fail_after: u32,
+
+        // Passes in a row before a down target is reported up again
+        #[arg(long, value_name = "N", default_value_t = 2)]
+        recover_after: u32,
+
+        // stdout, jsonl:PATH or webhook:URL (repeatable; default stdout)
+        #[arg(long, value_name = "SINK")]
+        alert: Vec<AlertSink>,
+
+        // Stop after this many probes per target instead of running forever
+        #[arg(long, value_name = "N")]
+        rounds: Option<u32>,
+    },
+
+    // Fetch -> parse -> store pages through a staged pipeline, one JSON
+    // line per page; failures are reported as dead letters on stderr
+    Pipeline {
+        // URLs to fetch (in addition to --file)
+        urls: Vec<String>,
+
+        // Read URLs from a file, one per line (`-` = stdin)
+        #[arg(short, long, value_name = "PATH")]
+        file: Option<PathBuf>,
+
+        // Pages fetched at once
+        #[arg(long, value_name = "N", default_value_t = 8)]
+        fetchers: usize,
+
+        // Pages parsed at once
+        #[arg(long, value_name = "N", default_value_t = 2)]
+        parsers: usize,
+
+        // Store pages in input order instead of as they finish
+        #[arg(long)]
+        input_order: bool,
+
+        // Write the JSONL output here instead of stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+
+    // Load-test one URL and report latency percentiles and status counts
+    Bench {
+        // Defaults to the lesson08 server on its default port
+        #[arg(default_value = "http://127.0.0.1:8080/")]
+        url: String,
+
+        // Closed model: this many connections, each sending back to back
+        #[arg(short, long, default_value_t = 16, conflicts_with = "rate")]
+        concurrency: usize,
+
+        // Open model: start this many requests per second regardless of
+        // how fast they complete
+        #[arg(short, long, value_name = "PER_SEC", value_parser = limit::parse_rate)]
+        rate: Option<f64>,
+
+        // Open model: drop requests past this many in flight
+        #[arg(long, value_name = "N", default_value_t = 1000, requires = "rate")]
+        max_in_flight: usize,
+
+        // Measure for this many seconds
+        #[arg(
+            short,
+            long,
+            value_name = "SECS",
+            default_value = "10",
+            value_parser = bench::parse_duration,
+            conflicts_with = "requests"
+        )]
+        duration: Duration,
+
+        // Stop after this many measured requests instead
+        #[arg(short = 'n', long, value_name = "N")]
+        requests: Option<u64>,
 
-async fn say_after(msg: &str, delay_ms: u64) {
-    sleep(Duration::from_millis(delay_ms)).await;
-    println!("{msg}");
+        // Send unmeasured requests for this many seconds first
+        #[arg(long, value_name = "SECS", default_value = "0", value_parser = bench::parse_secs)]
+        warmup: Duration,
+
+        // Per-request timeout in seconds
+        #[arg(long, value_name = "SECS", default_value_t = 10)]
+        timeout: u64,
+
+        // Print the report as JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+
+    // Pull every item from a paginated JSON API, one JSON line per item.
+    // Follows `Link: rel="next"` headers unless --cursor or --page-param
+    // says otherwise
+    Api {
+        url: String,
+
+        // bearer:TOKEN or basic:USER[:PASSWORD]
+        #[arg(long, value_name = "SCHEME:CREDENTIALS")]
+        auth: Option<Auth>,
+
+        // JSON pointer to the item array in each page (default: the page
+        // itself is the array)
+        #[arg(long, value_name = "POINTER")]
+        items: Option<String>,
+
+        // JSON pointer to the next-page cursor in each page
+        #[arg(long, value_name = "POINTER", conflicts_with = "page_param")]
+        cursor: Option<String>,
+
+        // Query parameter the cursor is sent back in
+        #[arg(
+            long,
+            value_name = "NAME",
+            default_value = "cursor",
+            requires = "cursor"
+        )]
+        cursor_param: String,
+
+        // Number pages through this query parameter, starting at 1
+        #[arg(long, value_name = "NAME")]
+        page_param: Option<String>,
+
+        // Stop after this many pages
+        #[arg(long, value_name = "N")]
+        max_pages: Option<usize>,
+
+        // Fetch just this URL and print its JSON as it is
+        #[arg(long, conflicts_with_all = ["items", "cursor", "page_param", "max_pages"])]
+        single: bool,
+    },
+}
+
+// What the `pipeline` parse stage keeps of a page
+#[derive(serde::Serialize)]
+struct PageSummary {
+    url: String,
+    bytes: usize,
+    title: Option<String>,
+    links: usize,
 }
 
-async fn worker(name: &str, delay_ms: u64) {
+fn summarize_page(url: String, body: &str) -> PageSummary {
+    let lower = body.to_ascii_lowercase();
+    let title = lower.find("<title").and_then(|start| {
+        let open_end = start + lower[start..].find('>')? + 1;
+        let close = open_end + lower[open_end..].find("</title")?;
+        Some(body[open_end..close].trim().to_string())
+    });
+    PageSummary {
+        url,
+        bytes: body.len(),
+        title,
+        links: crawl::extract_links(body).len(),
+    }
+}
+
+// Print `msg` after the delay, unless cancelled first
+async fn say_after(msg: &str, delay_ms: u64, token: &CancellationToken) {
+    tokio::select! {
+        _ = sleep(Duration::from_millis(delay_ms)) => println!("{msg}"),
+        _ = token.cancelled() => {}
+    }
+}
+
+async fn worker(name: &str, delay_ms: u64, token: &CancellationToken) {
     for i in 1..=5 {
         println!("[{name}] line {i}");
-        sleep(Duration::from_millis(delay_ms)).await;
+        tokio::select! {
+            _ = sleep(Duration::from_millis(delay_ms)) => {}
+            _ = token.cancelled() => {
+                println!("[{name}] cancelled");
+                return;
+            }
+        }
     }
     println!("[{name}] done")
 }
 
-// Fetch the entire body and return its length in bytes
-async fn fetch_len(client: &Client, url: &str) -> Result<usize> {
-    // GET request
-    let resp = client
-        .get(url)
-        .send()
-        .await
-        .with_context(|| format!("request failed: GET {url}"))?;
-
-    // Read the body fully into memory
-    let status = resp.status();
-    let body = resp
-        .bytes()
-        .await
-        .with_context(|| format!("read body failed: {url}"))?;
-
-    // Treat non-2xx as an error
-    if !status.is_success() {
-        anyhow::bail!("{url} -> HTTP {status}");
+// Read a URL list from a file, or from stdin for `-`
+async fn read_url_file(path: &PathBuf) -> Result<Vec<String>> {
+    let text = if path.as_os_str() == "-" {
+        let mut buf = String::new();
+        tokio::io::stdin()
+            .read_to_string(&mut buf)
+            .await
+            .context("failed to read URLs from stdin")?;
+        buf
+    } else {
+        tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("failed to read {}", path.display()))?
+    };
+    Ok(batch::parse_url_list(&text))
+}
+
+// Print each result as it arrives, then the summary
+async fn print_results(mut results: FetchMany) {
+    while let Some((url, res)) = results.next().await {
+        match res {
+            Ok(f) if f.from_cache => println!("{url} -> {} bytes (from cache)", f.len),
+            Ok(f) => println!("{url} -> {} bytes", f.len),
+            Err(e) => eprintln!("{url} -> ERROR: {e:#}"),
+        }
     }
+    println!("{}", results.summary());
+}
+
+// Stream a single URL, reporting progress on stderr as chunks arrive
+async fn get(client: Client, url: String, opts: StreamOptions) -> Result<()> {
+    let (tx, mut rx) = watch::channel(Progress::default());
+    let opts = StreamOptions {
+        progress: Some(tx),
+        ..opts
+    };
+    let reporter = tokio::spawn(async move {
+        while rx.changed().await.is_ok() {
+            let p = *rx.borrow_and_update();
+            match p.total {
+                Some(total) => eprint!("\r{} / {total} bytes", p.received),
+                None => eprint!("\r{} bytes", p.received),
+            }
+        }
+        eprintln!();
+    });
+
+    let res = fetch::fetch_stream(&client, &url, &opts).await;
+    // Dropping the sender ends the reporter loop
+    drop(opts);
+    let _ = reporter.await;
 
-    Ok(body.len())
+    let streamed = res?;
+    println!("{url} -> {} bytes", streamed.len);
+    if let Some(hash) = streamed.sha256 {
+        println!("sha256: {hash}");
+    }
+    Ok(())
 }
 
-// Wrap a single fetch with a timeout and return (url, result).
-async fn fetch_task(client: &Client, url: &str) -> (String, Result<usize>) {
-    let fut = fetch_len(&client, url);
-    let res = timeout(Duration::from_secs(10), fut)
-        .await
-        .map_err(|_| anyhow::anyhow!("timeout"))
-        .flatten();
+async fn demo(client: Client) {
+    let root = Supervisor::default();
+    let token = root.token();
+    say_after("Hi after 1s", 1000, &token).await;
+    say_after("Hi after 2s", 2000, &token).await;
+
+    // The workers are a subtree of `root`: Ctrl-C cancels them all, any that
+    // ignore it are aborted after the deadline, and one failing for good
+    // stops the rest
+    let mut workers = root.child().fail_fast();
+    let stopped = workers.token();
+    let retry_once = Restart::OnFailure { max_restarts: 1 };
+    let specs = [
+        ("A (200ms)", 200, retry_once),
+        ("B (300ms)", 300, retry_once),
+        ("C (600ms)", 600, Restart::Never),
+    ];
+    for (name, delay_ms, restart) in specs {
+        workers.spawn(name, restart, move |token| async move {
+            worker(name, delay_ms, &token).await;
+            Ok(())
+        });
+    }
+    for report in workers.run_until_ctrl_c(Duration::from_secs(2)).await {
+        let name = report.name;
+        match report.exit {
+            Exit::Ok => {}
+            Exit::Failed(e) => eprintln!("[{name}] failed: {e:#}"),
+            Exit::Panicked(msg) => eprintln!("[{name}] panicked: {msg}"),
+            other => eprintln!("[{name}] {other:?}"),
+        }
+        if report.restarts > 0 {
+            eprintln!("[{name}] restarted {} time(s)", report.restarts);
+        }
+    }
+
+    if stopped.is_cancelled() {
+        println!("Workers stopped early, skipping the fetch demo");
+        return;
+    }
+    println!("All tasks finished");
+
+    // Sample URLs — replace with your own, or use the `fetch` subcommand
+    let urls = [
+        "https://example.com",
+        "https://www.rust-lang.org/",
+        "https://httpbin.org/bytes/2048",
+    ];
 
-    (url.to_string(), res)
+    // Run them concurrently and print each result (length or error)
+    let opts = BatchOptions {
+        concurrency: 3,
+        order: Order::Input,
+        ..BatchOptions::default()
+    };
+    print_results(fetch_many(client, urls, &opts)).await;
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    say_after("Hi after 1s", 1000).await;
-    say_after("Hi after 2s", 2000).await;
+    let cli = Cli::parse();
 
-    let t1 = worker("A (200ms)", 200);
-    let t2 = worker("B (300ms)", 300);
-    let t3 = worker("C (600ms)", 600);
+    // One shared client; pooling, protocol, proxy and DNS come from the flags
+    let config = ClientConfig {
+        pool_max_idle_per_host: cli.pool_max_idle,
+        pool_idle_timeout: Duration::from_secs(cli.pool_idle_timeout),
+        http: cli.http,
+        user_agent: cli.user_agent.clone(),
+        proxy: cli.proxy.clone(),
+        resolve: cli.resolve.clone(),
+    };
+    let client = config.build()?;
 
-    // Run all three concurrently and wait for all to finish
-    tokio::join!(t1, t2, t3);
-
-    println!("All tasks finished");
+    match cli.command {
+        None => demo(client).await,
+        Some(Command::Fetch {
+            mut urls,
+            file,
+            concurrency,
+            input_order,
+            retries,
+            budget,
+            rate,
+            max_per_host,
+            cache_dir,
+            offline,
+            diag,
+        }) => {
+            if let Some(path) = &file {
+                urls.extend(read_url_file(path).await?);
+            }
+            if urls.is_empty() {
+                anyhow::bail!("no URLs given (pass them as arguments or via --file)");
+            }
+            if diag {
+                if config.proxy.is_some() {
+                    anyhow::bail!("--diag connects directly and can't go through --proxy");
+                }
+                let mut reports = futures::stream::iter(urls)
+                    .map(|url| {
+                        let config = &config;
+                        async move { diag::diagnose(config, &url, Duration::from_secs(10)).await }
+                    })
+                    .buffered(concurrency.max(1));
+                while let Some(d) = reports.next().await {
+                    println!("{d}");
+                }
+                return Ok(());
+            }
+            let order = if input_order {
+                Order::Input
+            } else {
+                Order::Completion
+            };
+            let retry = (retries > 0).then(|| RetryPolicy {
+                max_attempts: retries + 1,
+                budget: Duration::from_secs(budget),
+                ..RetryPolicy::default()
+            });
+            let limits = HostLimits {
+                per_second: rate,
+                max_concurrent: max_per_host,
+            };
+            let limiter =
+                (rate.is_some() || max_per_host.is_some()).then(|| HostLimiter::new(limits));
+            let cache = match &cache_dir {
+                Some(dir) => Some(Arc::new(Cache::open(dir, offline)?)),
+                None => None,
+            };
+            let opts = BatchOptions {
+                concurrency,
+                order,
+                retry,
+                limiter,
+                cache,
+            };
+            print_results(fetch_many(client, urls, &opts)).await;
+        }
+        Some(Command::Get {
+            url,
+            output,
+            sha256,
+            expect_sha256,
+            max_bytes,
+        }) => {
+            let opts = StreamOptions {
+                max_bytes,
+                sha256,
+                expect_sha256,
+                save_to: output,
+                progress: None,
+            };
+            get(client, url, opts).await?;
+        }
+        Some(Command::Download {
+            url,
+            output,
+            parts,
+            sha256,
+        }) => {
+            let opts = DownloadOptions {
+                parts,
+                sha256,
+                ..DownloadOptions::default()
+            };
+            let report = download::download(&client, &url, &output, &opts).await?;
+            println!(
+                "{url} -> {} ({} bytes, {} range(s){})",
+                output.display(),
+                report.bytes,
+                report.ranges,
+                if report.resumed { ", resumed" } else { "" }
+            );
+        }
+        Some(Command::Crawl {
+            seeds,
+            depth,
+            max_pages,
+            allow_domains,
+            delay_ms,
+            concurrency,
+            ignore_robots,
+            output,
+        }) => {
+            if seeds.is_empty() {
+                anyhow::bail!("no seed URLs given");
+            }
+            let opts = CrawlOptions {
+                max_depth: depth,
+                max_pages,
+                allowed_domains: allow_domains,
+                per_host_delay: Duration::from_millis(delay_ms),
+                concurrency,
+                respect_robots: !ignore_robots,
+                user_agent: config.user_agent.clone(),
+                ..CrawlOptions::default()
+            };
+            let stats = match &output {
+                Some(path) => {
+                    let file = std::fs::File::create(path)
+                        .with_context(|| format!("failed to create {}", path.display()))?;
+                    let mut out = std::io::BufWriter::new(file);
+                    crawl::crawl(&client, &seeds, &opts, &mut out).await?
+                }
+                None => crawl::crawl(&client, &seeds, &opts, &mut std::io::stdout().lock()).await?,
+            };
+            eprintln!(
+                "pages: {}, errors: {}, blocked by robots.txt: {}",
+                stats.pages, stats.errors, stats.blocked
+            );
+        }
+        Some(Command::Monitor {
+            urls,
+            config: targets_file,
+            interval,
+            timeout,
+            expect_status,
+            contains,
+            regex,
+            max_latency_ms,
+            fail_after,
+            recover_after,
+            mut alert,
+            rounds,
+        }) => {
+            let mut targets: Vec<Target> = match &targets_file {
+                Some(path) => {
+                    let json = tokio::fs::read(path)
+                        .await
+                        .with_context(|| format!("failed to read {}", path.display()))?;
+                    serde_json::from_slice(&json)
+                        .with_context(|| format!("bad target list in {}", path.display()))?
+                }
+                None => Vec::new(),
+            };
+            targets.extend(urls.into_iter().map(|url| Target {
+                name: None,
+                url,
+                expect_status,
+                body_contains: contains.clone(),
+                body_regex: regex.clone(),
+                max_latency_ms,
+                interval_ms: None,
+            }));
+            if targets.is_empty() {
+                anyhow::bail!("no targets given (pass URLs or --config)");
+            }
+            let probes = targets
+                .into_iter()
+                .map(Probe::new)
+                .collect::<Result<Vec<_>>>()?;
+            if alert.is_empty() {
+                alert.push(AlertSink::Stdout);
+            }
+            let opts = MonitorOptions {
+                interval: Duration::from_secs(interval),
+                timeout: Duration::from_secs(timeout),
+                fail_after,
+                recover_after,
+                rounds,
+            };
+            monitor::run(&client, probes, &opts, &alert).await?;
+        }
+        Some(Command::Api {
+            url,
+            auth,
+            items,
+            cursor,
+            cursor_param,
+            page_param,
+            max_pages,
+            single,
+        }) => {
+            let auth = auth.unwrap_or_default();
+            if single {
+                let body: serde_json::Value = api::fetch_json(&client, &url, &auth).await?;
+                println!("{body:#}");
+                return Ok(());
+            }
+            let paging = match (cursor, page_param) {
+                (Some(field), _) => Paging::Cursor {
+                    field,
+                    param: cursor_param,
+                },
+                (None, Some(param)) => Paging::Page { param, start: 1 },
+                (None, None) => Paging::Link,
+            };
+            let opts = PageOptions {
+                paging,
+                items,
+                auth,
+                max_pages,
+            };
+            let mut out = std::io::stdout().lock();
+            let mut count = 0;
+            let mut items = std::pin::pin!(api::paginate::<serde_json::Value>(client, &url, opts));
+            while let Some(item) = items.next().await {
+                writeln!(out, "{}", item?)?;
+                count += 1;
+            }
+            eprintln!("{count} item(s)");
+        }
+        Some(Command::Pipeline {
+            mut urls,
+            file,
+            fetchers,
+            parsers,
+            input_order,
+            output,
+        }) => {
+            if let Some(path) = &file {
+                urls.extend(read_url_file(path).await?);
+            }
+            if urls.is_empty() {
+                anyhow::bail!("no URLs given (pass them as arguments or via --file)");
+            }
+            let mut out: Box<dyn std::io::Write> = match &output {
+                Some(path) => Box::new(std::io::BufWriter::new(
+                    std::fs::File::create(path)
+                        .with_context(|| format!("failed to create {}", path.display()))?,
+                )),
+                None => Box::new(std::io::stdout().lock()),
+            };
+            let stage = |parallelism| StageOptions {
+                parallelism,
+                ordered: input_order,
+                ..StageOptions::default()
+            };
 
-    // Build a reqwest client with a UA and a small pool idle timeout
-    let client = Client::builder()
-        .pool_idle_timeout(Duration::from_secs(15))
-        .build()?;
-
-    // Three sample URLs — replace with your own
-    let u1 = "https://example.com";
-    let u2 = "https://www.rust-lang.org/";
-    let u3 = "https://httpbin.org/bytes/2048";
-
-    // Run all three tasks concurrently and wait for all to finish
-    let (r1, r2, r3) = tokio::join!(
-        fetch_task(&client, u1),
-        fetch_task(&client, u2),
-        fetch_task(&client, u3),
-    );
-
-    // Print each result (length or error)
-    for (url, res) in [r1, r2, r3] {
-        match res {
-            Ok(len) => println!("{url} -> {len} bytes"),
-            Err(e) => eprintln!("{url} -> ERROR: {e:#}"),
+            let stats = Pipeline::from_iter(urls, 16)
+                .stage("fetch", stage(fetchers), move |url: String| {
+                    let client = client.clone();
+                    async move {
+                        let body = fetch::fetch_text(&client, &url).await?;
+                        Ok((url, body))
+                    }
+                })
+                .stage(
+                    "parse",
+                    stage(parsers),
+                    |(url, body): (String, String)| async move { Ok(summarize_page(url, &body)) },
+                )
+                .run(
+                    |page| {
+                        // The write itself is synchronous; the future just
+                        // hands back its result
+                        let res = serde_json::to_writer(&mut out, &page)
+                            .map_err(anyhow::Error::from)
+                            .and_then(|()| Ok(writeln!(out)?));
+                        async move { res }
+                    },
+                    |d| eprintln!("dead letter: {} #{}: {:#}", d.stage, d.seq, d.error),
+                )
+                .await;
+            out.flush()?;
+            for s in stats {
+                eprintln!("{s}");
+            }
+        }
+        Some(Command::Bench {
+            url,
+            concurrency,
+            rate,
+            max_in_flight,
+            duration,
+            requests,
+            warmup,
+            timeout,
+            json,
+        }) => {
+            let model = match rate {
+                Some(rate) => Model::Open {
+                    rate,
+                    max_in_flight,
+                },
+                None => Model::Closed { concurrency },
+            };
+            let limit = match requests {
+                Some(n) => Limit::Requests(n),
+                None => Limit::Duration(duration),
+            };
+            let opts = BenchOptions {
+                model,
+                limit,
+                warmup,
+                timeout: Duration::from_secs(timeout),
+            };
+            let report = bench::run(&client, &url, &opts).await;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                println!("{report}");
+            }
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::task::yield_now;
+    use tokio::time::{Instant, advance, pause};
+
+    use super::*;
+
+    // The timer wheel has 1ms slots and rounds each deadline up, so every
+    // sleep may end up to 1ms late
+    fn assert_took(start: Instant, expected_ms: u128, sleeps: u128) {
+        let ms = start.elapsed().as_millis();
+        assert!(
+            (expected_ms..=expected_ms + sleeps).contains(&ms),
+            "took {ms}ms, expected {expected_ms}ms"
+        );
+    }
+
+    #[tokio::test]
+    async fn say_after_sleeps_exactly_its_delay() {
+        pause();
+        let start = Instant::now();
+        let token = CancellationToken::new();
+        let task = tokio::spawn(async move { say_after("hi", 1000, &token).await });
+        // Let it start its sleep before moving the clock
+        yield_now().await;
+
+        advance(Duration::from_millis(999)).await;
+        yield_now().await;
+        assert!(!task.is_finished());
+
+        advance(Duration::from_millis(1)).await;
+        task.await.unwrap();
+        assert_took(start, 1000, 1);
+    }
+
+    #[tokio::test]
+    async fn worker_runs_five_steps_or_stops_when_cancelled() {
+        pause();
+        let start = Instant::now();
+        worker("full", 200, &CancellationToken::new()).await;
+        assert_took(start, 1000, 5);
+
+        let start = Instant::now();
+        let token = CancellationToken::new();
+        let stop = token.clone();
+        let task = tokio::spawn(async move { worker("cut", 200, &token).await });
+        yield_now().await;
+        advance(Duration::from_millis(450)).await;
+        stop.cancel();
+        task.await.unwrap();
+        // Cancelled mid-sleep, so it returns right at the cancel
+        assert_eq!(start.elapsed(), Duration::from_millis(450));
+    }
+}
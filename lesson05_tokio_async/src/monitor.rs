@@ -0,0 +1,583 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use futures::StreamExt;
+use regex::Regex;
+use reqwest::Client;
+use reqwest::header::CONTENT_TYPE;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tokio::time::{Instant, MissedTickBehavior, interval, timeout};
+
+use crate::supervisor::{Restart, Supervisor};
+
+// Only this much of a body is kept for substring / regex checks
+const MAX_CHECKED_BODY: usize = 1024 * 1024;
+
+// One endpoint to watch, as written in the `--config` JSON list
+#[derive(Deserialize, Clone, Debug)]
+pub struct Target {
+    // Defaults to the URL
+    pub name: Option<String>,
+    pub url: String,
+    #[serde(default = "default_status")]
+    pub expect_status: u16,
+    pub body_contains: Option<String>,
+    pub body_regex: Option<String>,
+    pub max_latency_ms: Option<u64>,
+    // Overrides the monitor-wide interval
+    pub interval_ms: Option<u64>,
+}
+
+fn default_status() -> u16 {
+    200
+}
+
+// A target with its regex compiled
+pub struct Probe {
+    pub name: String,
+    target: Target,
+    regex: Option<Regex>,
+}
+
+impl Probe {
+    pub fn new(target: Target) -> Result<Self> {
+        // A zero period would make the probe's ticker panic
+        anyhow::ensure!(
+            target.interval_ms != Some(0),
+            "interval_ms for {} must be at least 1",
+            target.url
+        );
+        let regex = match &target.body_regex {
+            Some(re) => {
+                Some(Regex::new(re).with_context(|| format!("bad regex for {}", target.url))?)
+            }
+            None => None,
+        };
+        Ok(Self {
+            name: target.name.clone().unwrap_or_else(|| target.url.clone()),
+            target,
+            regex,
+        })
+    }
+
+    // Why the response doesn't pass, or None when it does
+    fn check(&self, status: u16, body: &str, latency: Duration) -> Option<String> {
+        let t = &self.target;
+        if status != t.expect_status {
+            return Some(format!("HTTP {status}, expected {}", t.expect_status));
+        }
+        if let Some(s) = &t.body_contains
+            && !body.contains(s.as_str())
+        {
+            return Some(format!("body does not contain {s:?}"));
+        }
+        if let Some(re) = &self.regex
+            && !re.is_match(body)
+        {
+            return Some(format!("body does not match /{re}/"));
+        }
+        if let Some(max) = t.max_latency_ms
+            && latency > Duration::from_millis(max)
+        {
+            return Some(format!("slow: {}ms > {max}ms", latency.as_millis()));
+        }
+        None
+    }
+
+    fn needs_body(&self) -> bool {
+        self.target.body_contains.is_some() || self.regex.is_some()
+    }
+}
+
+#[derive(Debug)]
+pub struct ProbeResult {
+    pub status: Option<u16>,
+    pub latency: Duration,
+    // Set when the probe failed
+    pub reason: Option<String>,
+}
+
+// One request with the usual timeout and error context. Unlike `fetch_task`
+// a non-2xx status isn't an error here: it's checked against the target.
+async fn probe(client: &Client, p: &Probe, limit: Duration) -> ProbeResult {
+    let url = &p.target.url;
+    let fetch = async {
+        let resp = client
+            .get(url)
+            .send()
+            .await
+            .with_context(|| format!("request failed: GET {url}"))?;
+        let status = resp.status().as_u16();
+        let mut body = Vec::new();
+        let mut stream = resp.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.with_context(|| format!("read body failed: {url}"))?;
+            if p.needs_body() && body.len() < MAX_CHECKED_BODY {
+                body.extend_from_slice(&chunk);
+            }
+        }
+        anyhow::Ok((status, String::from_utf8_lossy(&body).into_owned()))
+    };
+
+    let start = Instant::now();
+    let res = timeout(limit, fetch).await;
+    let latency = start.elapsed();
+    match res {
+        Err(_) => ProbeResult {
+            status: None,
+            latency,
+            reason: Some(format!("timeout after {}s", limit.as_secs_f32())),
+        },
+        Ok(Err(e)) => ProbeResult {
+            status: None,
+            latency,
+            reason: Some(format!("{e:#}")),
+        },
+        Ok(Ok((status, body))) => ProbeResult {
+            status: Some(status),
+            latency,
+            reason: p.check(status, &body, latency),
+        },
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum State {
+    Up,
+    Down,
+}
+
+#[derive(Default)]
+struct TargetState {
+    state: Option<State>,
+    // Consecutive results disagreeing with `state`
+    streak: u32,
+}
+
+// Turns a stream of pass/fail results into up/down transitions. A target
+// only flips after `fail_after` failures (or `recover_after` passes) in a
+// row, so a single blip doesn't alert.
+pub struct Tracker {
+    fail_after: u32,
+    recover_after: u32,
+    targets: Vec<TargetState>,
+}
+
+impl Tracker {
+    pub fn new(targets: usize, fail_after: u32, recover_after: u32) -> Self {
+        Self {
+            fail_after: fail_after.max(1),
+            recover_after: recover_after.max(1),
+            targets: (0..targets).map(|_| TargetState::default()).collect(),
+        }
+    }
+
+    // The new state when target `idx` changes; coming up for the first
+    // time is not a transition worth reporting
+    pub fn observe(&mut self, idx: usize, ok: bool) -> Option<State> {
+        let t = &mut self.targets[idx];
+        let seen = if ok { State::Up } else { State::Down };
+        if t.state == Some(seen) {
+            t.streak = 0;
+            return None;
+        }
+        t.streak += 1;
+        let needed = match (t.state, seen) {
+            (None, State::Up) => 1,
+            (_, State::Down) => self.fail_after,
+            (_, State::Up) => self.recover_after,
+        };
+        if t.streak < needed {
+            return None;
+        }
+        let first = t.state.is_none();
+        t.state = Some(seen);
+        t.streak = 0;
+        (!first || seen == State::Down).then_some(seen)
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Alert {
+    pub name: String,
+    pub url: String,
+    pub state: State,
+    // Unix seconds
+    pub at: u64,
+    pub status: Option<u16>,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+// Where alerts go: `stdout`, `jsonl:PATH` or `webhook:URL`
+#[derive(Clone, Debug)]
+pub enum AlertSink {
+    Stdout,
+    Jsonl(PathBuf),
+    // POSTs each alert as JSON
+    Webhook(String),
+}
+
+impl FromStr for AlertSink {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "stdout" {
+            return Ok(AlertSink::Stdout);
+        }
+        match s.split_once(':') {
+            Some(("jsonl", path)) if !path.is_empty() => Ok(AlertSink::Jsonl(path.into())),
+            Some(("webhook", url)) if url.starts_with("http") => Ok(AlertSink::Webhook(url.into())),
+            _ => Err(format!(
+                "expected stdout, jsonl:PATH or webhook:URL, got `{s}`"
+            )),
+        }
+    }
+}
+
+impl AlertSink {
+    // `limit` caps the webhook POST, so a hung endpoint can't hold up the
+    // sinks after it
+    async fn emit(&self, client: &Client, alert: &Alert, limit: Duration) -> Result<()> {
+        match self {
+            AlertSink::Stdout => {
+                let state = match alert.state {
+                    State::Up => "UP",
+                    State::Down => "DOWN",
+                };
+                match &alert.reason {
+                    Some(reason) => println!("[ALERT] {} is {state}: {reason}", alert.name),
+                    None => println!("[ALERT] {} is {state}", alert.name),
+                }
+            }
+            AlertSink::Jsonl(path) => {
+                let mut line = serde_json::to_vec(alert)?;
+                line.push(b'\n');
+                let mut file = tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await
+                    .with_context(|| format!("failed to open {}", path.display()))?;
+                file.write_all(&line).await?;
+                // tokio finishes writes in the background; make sure this
+                // one lands before the file is dropped
+                file.flush().await?;
+            }
+            AlertSink::Webhook(url) => {
+                let send = client
+                    .post(url)
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(serde_json::to_vec(alert)?)
+                    .send();
+                let res = match timeout(limit, send).await {
+                    Ok(res) => res
+                        .and_then(|r| r.error_for_status())
+                        .map_err(anyhow::Error::new),
+                    Err(_) => Err(anyhow::anyhow!("timeout after {}s", limit.as_secs_f32())),
+                };
+                res.with_context(|| format!("webhook failed: POST {url}"))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct MonitorOptions {
+    // Between probes of one target, unless the target sets its own
+    pub interval: Duration,
+    pub timeout: Duration,
+    pub fail_after: u32,
+    pub recover_after: u32,
+    // Stop after this many probes per target (runs until Ctrl-C otherwise)
+    pub rounds: Option<u32>,
+}
+
+impl Default for MonitorOptions {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(30),
+            timeout: Duration::from_secs(10),
+            fail_after: 2,
+            recover_after: 2,
+            rounds: None,
+        }
+    }
+}
+
+// Probe every target on its own schedule until Ctrl-C (or `rounds`), and
+// send an alert to every sink on each up/down transition. Alerts go out
+// from their own loop so slow sinks never hold up probe results.
+pub async fn run(
+    client: &Client,
+    probes: Vec<Probe>,
+    opts: &MonitorOptions,
+    sinks: &[AlertSink],
+) -> Result<()> {
+    anyhow::ensure!(
+        !opts.interval.is_zero(),
+        "monitor interval must not be zero"
+    );
+    let probes: Vec<Arc<Probe>> = probes.into_iter().map(Arc::new).collect();
+    let (tx, mut rx) = mpsc::channel::<(usize, ProbeResult)>(64);
+
+    let mut sup = Supervisor::default();
+    for (idx, p) in probes.iter().enumerate() {
+        let every = p
+            .target
+            .interval_ms
+            .map_or(opts.interval, Duration::from_millis);
+        let (client, p, tx) = (client.clone(), p.clone(), tx.clone());
+        let (limit, rounds) = (opts.timeout, opts.rounds);
+        sup.spawn(&p.name.clone(), Restart::Never, move |token| {
+            let (client, p, tx) = (client.clone(), p.clone(), tx.clone());
+            async move {
+                let mut ticks = interval(every);
+                ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                let mut done = 0;
+                while rounds.is_none_or(|r| done < r) {
+                    let res = tokio::select! {
+                        _ = token.cancelled() => break,
+                        res = async {
+                            ticks.tick().await;
+                            probe(&client, &p, limit).await
+                        } => res,
+                    };
+                    if tx.send((idx, res)).await.is_err() {
+                        break;
+                    }
+                    done += 1;
+                }
+                Ok(())
+            }
+        });
+    }
+    // The channel closes once every probe loop has stopped
+    drop(tx);
+
+    let (alert_tx, mut alert_rx) = mpsc::unbounded_channel::<Alert>();
+    let deliver = async {
+        while let Some(alert) = alert_rx.recv().await {
+            // A broken sink shouldn't stop the monitor
+            for sink in sinks {
+                if let Err(e) = sink.emit(client, &alert, opts.timeout).await {
+                    eprintln!("alert to {sink:?} failed: {e:#}");
+                }
+            }
+        }
+    };
+
+    let mut tracker = Tracker::new(probes.len(), opts.fail_after, opts.recover_after);
+    let track = async move {
+        while let Some((idx, res)) = rx.recv().await {
+            let p = &probes[idx];
+            let status = res.status.map_or("-".to_string(), |s| s.to_string());
+            match &res.reason {
+                None => eprintln!("{}: ok {status} {}ms", p.name, res.latency.as_millis()),
+                Some(r) => eprintln!(
+                    "{}: FAIL {status} {}ms: {r}",
+                    p.name,
+                    res.latency.as_millis()
+                ),
+            }
+
+            let Some(state) = tracker.observe(idx, res.reason.is_none()) else {
+                continue;
+            };
+            let alert = Alert {
+                name: p.name.clone(),
+                url: p.target.url.clone(),
+                state,
+                at: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs()),
+                status: res.status,
+                latency_ms: res.latency.as_millis() as u64,
+                reason: res.reason,
+            };
+            // Transitions are rare, so the queue stays short
+            let _ = alert_tx.send(alert);
+        }
+    };
+    tokio::join!(sup.run_until_ctrl_c(opts.timeout), track, deliver);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::test_server::{Route, TestServer};
+
+    #[test]
+    fn flaps_are_suppressed() {
+        let mut t = Tracker::new(1, 2, 2);
+        // Coming up is silent, single failures in between are ignored
+        let seq = [true, false, true, false, true];
+        assert!(seq.iter().all(|ok| t.observe(0, *ok).is_none()));
+        assert_eq!(t.observe(0, false), None);
+        assert_eq!(t.observe(0, false), Some(State::Down));
+        assert_eq!(t.observe(0, true), None);
+        assert_eq!(t.observe(0, true), Some(State::Up));
+    }
+
+    #[tokio::test]
+    async fn zero_intervals_are_rejected() {
+        let target = Target {
+            name: None,
+            url: "http://127.0.0.1:9/".into(),
+            expect_status: 200,
+            body_contains: None,
+            body_regex: None,
+            max_latency_ms: None,
+            interval_ms: Some(0),
+        };
+        let err = Probe::new(target.clone()).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "interval_ms for http://127.0.0.1:9/ must be at least 1"
+        );
+
+        let probe = Probe::new(Target {
+            interval_ms: None,
+            ..target
+        })
+        .unwrap();
+        let opts = MonitorOptions {
+            interval: Duration::ZERO,
+            ..MonitorOptions::default()
+        };
+        let sinks = [AlertSink::Stdout];
+        assert!(
+            run(&Client::new(), vec![probe], &opts, &sinks)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn probes_check_body_and_alert_on_transitions() {
+        let srv = TestServer::start(HashMap::new()).await;
+        let healthy = || Route::ok(r#"{"status":"ok","version":"1.2.3"}"#);
+        let broken = Route {
+            status: 500,
+            ..Route::default()
+        };
+        srv.set_sequence(
+            "/health",
+            vec![
+                healthy(),
+                broken.clone(),
+                Route::ok(r#"{"status":"degraded"}"#),
+                healthy(),
+                healthy(),
+            ],
+        );
+
+        let target = Target {
+            name: Some("api".into()),
+            url: srv.url("/health"),
+            expect_status: 200,
+            body_contains: Some(r#""status":"ok""#.into()),
+            body_regex: Some(r#""version":"\d+\.\d+\.\d+""#.into()),
+            max_latency_ms: Some(5_000),
+            interval_ms: Some(5),
+        };
+        let path = std::env::temp_dir().join(format!("monitor-{}.jsonl", srv.addr.port()));
+        let opts = MonitorOptions {
+            rounds: Some(5),
+            ..MonitorOptions::default()
+        };
+        let sinks = [AlertSink::Jsonl(path.clone())];
+        run(
+            &Client::new(),
+            vec![Probe::new(target).unwrap()],
+            &opts,
+            &sinks,
+        )
+        .await
+        .unwrap();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let alerts: Vec<serde_json::Value> = text
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(alerts.len(), 2);
+        assert_eq!(alerts[0]["state"], "down");
+        assert_eq!(
+            alerts[0]["reason"],
+            r#"body does not contain "\"status\":\"ok\"""#
+        );
+        assert_eq!(alerts[1]["state"], "up");
+        assert_eq!(srv.requests().len(), 5);
+
+        assert!("jsonl:".parse::<AlertSink>().is_err());
+        assert!(matches!(
+            "webhook:http://h/x".parse::<AlertSink>(),
+            Ok(AlertSink::Webhook(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn hung_webhook_times_out_without_blocking_other_sinks() {
+        let srv = TestServer::start(HashMap::new()).await;
+        let broken = Route {
+            status: 500,
+            ..Route::default()
+        };
+        srv.set_sequence("/health", vec![broken.clone(), broken]);
+        srv.set_route(
+            "/hook",
+            Route {
+                delay: Some(Duration::from_secs(60)),
+                ..Route::ok("")
+            },
+        );
+
+        let target = Target {
+            name: Some("api".into()),
+            url: srv.url("/health"),
+            expect_status: 200,
+            body_contains: None,
+            body_regex: None,
+            max_latency_ms: None,
+            interval_ms: Some(5),
+        };
+        let path = std::env::temp_dir().join(format!("monitor-hook-{}.jsonl", srv.addr.port()));
+        let opts = MonitorOptions {
+            rounds: Some(2),
+            timeout: Duration::from_millis(300),
+            ..MonitorOptions::default()
+        };
+        let sinks = [
+            AlertSink::Webhook(srv.url("/hook")),
+            AlertSink::Jsonl(path.clone()),
+        ];
+        let start = Instant::now();
+        run(
+            &Client::new(),
+            vec![Probe::new(target).unwrap()],
+            &opts,
+            &sinks,
+        )
+        .await
+        .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let alert: serde_json::Value = serde_json::from_str(text.trim()).unwrap();
+        assert_eq!(alert["state"], "down");
+        assert!(srv.requests().contains(&"POST /hook".to_string()));
+    }
+}
@@ -0,0 +1,374 @@
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use anyhow::Result;
+use futures::stream::{self, StreamExt};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+
+// A value on its way through the pipeline, tagged with its position in the
+// source so order can be kept and dead letters traced back
+struct Item<T> {
+    seq: u64,
+    value: T,
+}
+
+// An item a stage (or the sink) gave up on
+#[derive(Debug)]
+pub struct DeadLetter {
+    pub stage: String,
+    // Position of the item in the source
+    pub seq: u64,
+    pub error: anyhow::Error,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct StageOptions {
+    // Items processed at once, each on its own task
+    pub parallelism: usize,
+    // Emit results in source order (a slow item holds back later ones)
+    pub ordered: bool,
+    // Capacity of the channel to the next stage; a full channel makes this
+    // stage wait, which is what pushes back on the source
+    pub buffer: usize,
+}
+
+impl Default for StageOptions {
+    fn default() -> Self {
+        Self {
+            parallelism: 4,
+            ordered: false,
+            buffer: 16,
+        }
+    }
+}
+
+struct StageMetrics {
+    name: String,
+    items_in: AtomicU64,
+    items_out: AtomicU64,
+    errors: AtomicU64,
+    started: Instant,
+    // Micros since `started` when the stage finished; 0 while running
+    finished_us: AtomicU64,
+}
+
+impl StageMetrics {
+    fn new(name: &str) -> Arc<Self> {
+        Arc::new(Self {
+            name: name.to_string(),
+            items_in: AtomicU64::new(0),
+            items_out: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            started: Instant::now(),
+            finished_us: AtomicU64::new(0),
+        })
+    }
+
+    fn finish(&self) {
+        let us = self.started.elapsed().as_micros() as u64;
+        self.finished_us.store(us.max(1), Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> StageStats {
+        let elapsed = match self.finished_us.load(Ordering::Relaxed) {
+            0 => self.started.elapsed(),
+            us => Duration::from_micros(us),
+        };
+        StageStats {
+            name: self.name.clone(),
+            items_in: self.items_in.load(Ordering::Relaxed),
+            items_out: self.items_out.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            elapsed,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct StageStats {
+    pub name: String,
+    pub items_in: u64,
+    pub items_out: u64,
+    pub errors: u64,
+    pub elapsed: Duration,
+}
+
+impl StageStats {
+    // Items passed on per second of the stage's lifetime
+    pub fn per_sec(&self) -> f64 {
+        self.items_out as f64 / self.elapsed.as_secs_f64().max(1e-6)
+    }
+}
+
+impl fmt::Display for StageStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: in {}, out {}, errors {}, {:.1}/s over {}ms",
+            self.name,
+            self.items_in,
+            self.items_out,
+            self.errors,
+            self.per_sec(),
+            self.elapsed.as_millis()
+        )
+    }
+}
+
+// source -> stage -> ... -> sink, each hop a bounded channel. Every stage
+// runs on its own task; errors from any stage or the sink are routed to the
+// dead-letter sink instead of stopping the flow.
+pub struct Pipeline<T> {
+    rx: mpsc::Receiver<Item<T>>,
+    tasks: JoinSet<()>,
+    metrics: Vec<Arc<StageMetrics>>,
+    dead: mpsc::Sender<DeadLetter>,
+    dead_rx: mpsc::Receiver<DeadLetter>,
+}
+
+impl<T: Send + 'static> Pipeline<T> {
+    pub fn from_iter<I>(items: I, buffer: usize) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        I::IntoIter: Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel(buffer.max(1));
+        let (dead, dead_rx) = mpsc::channel(buffer.max(1));
+        let metrics = StageMetrics::new("source");
+        let m = metrics.clone();
+        let mut tasks = JoinSet::new();
+        let items = items.into_iter();
+        tasks.spawn(async move {
+            for (seq, value) in (0u64..).zip(items) {
+                m.items_in.fetch_add(1, Ordering::Relaxed);
+                if tx.send(Item { seq, value }).await.is_err() {
+                    break;
+                }
+                m.items_out.fetch_add(1, Ordering::Relaxed);
+            }
+            m.finish();
+        });
+        Self {
+            rx,
+            tasks,
+            metrics: vec![metrics],
+            dead,
+            dead_rx,
+        }
+    }
+
+    // Add a map stage running `f` on up to `opts.parallelism` items at once
+    pub fn stage<U, F, Fut>(mut self, name: &str, opts: StageOptions, f: F) -> Pipeline<U>
+    where
+        U: Send + 'static,
+        F: Fn(T) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<U>> + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel(opts.buffer.max(1));
+        let metrics = StageMetrics::new(name);
+        let (m, dead, stage) = (metrics.clone(), self.dead.clone(), name.to_string());
+        let f = Arc::new(f);
+
+        let input = stream::unfold(self.rx, |mut rx| async move {
+            rx.recv().await.map(|item| (item, rx))
+        });
+        let m_in = m.clone();
+        let running = input.map(move |item: Item<T>| {
+            m_in.items_in.fetch_add(1, Ordering::Relaxed);
+            // Spawned so items really run in parallel, and so a panic in `f`
+            // becomes a dead letter instead of taking the stage down
+            let handle = tokio::spawn((f.clone())(item.value));
+            async move { (item.seq, handle.await) }
+        });
+        let parallelism = opts.parallelism.max(1);
+        let mut results = if opts.ordered {
+            running.buffered(parallelism).boxed()
+        } else {
+            running.buffer_unordered(parallelism).boxed()
+        };
+
+        self.tasks.spawn(async move {
+            while let Some((seq, res)) = results.next().await {
+                let error = match res {
+                    Ok(Ok(value)) => {
+                        if tx.send(Item { seq, value }).await.is_err() {
+                            break;
+                        }
+                        m.items_out.fetch_add(1, Ordering::Relaxed);
+                        continue;
+                    }
+                    Ok(Err(e)) => e,
+                    Err(e) => anyhow::anyhow!("stage panicked: {e}"),
+                };
+                m.errors.fetch_add(1, Ordering::Relaxed);
+                let letter = DeadLetter {
+                    stage: stage.clone(),
+                    seq,
+                    error,
+                };
+                if dead.send(letter).await.is_err() {
+                    break;
+                }
+            }
+            m.finish();
+        });
+
+        self.metrics.push(metrics);
+        Pipeline {
+            rx,
+            tasks: self.tasks,
+            metrics: self.metrics,
+            dead: self.dead,
+            dead_rx: self.dead_rx,
+        }
+    }
+
+    // Drain the pipeline into `sink`, one item at a time, while feeding
+    // dead letters to `dead_letter`. Returns per-stage stats, sink last.
+    pub async fn run<S, SFut, D>(self, mut sink: S, mut dead_letter: D) -> Vec<StageStats>
+    where
+        S: FnMut(T) -> SFut,
+        SFut: Future<Output = Result<()>>,
+        D: FnMut(DeadLetter),
+    {
+        let Pipeline {
+            mut rx,
+            mut tasks,
+            mut metrics,
+            dead,
+            mut dead_rx,
+        } = self;
+        // Stages hold their own senders; the dead-letter channel closes once
+        // they're all done
+        drop(dead);
+
+        let m = StageMetrics::new("sink");
+        let (mut items_open, mut dead_open) = (true, true);
+        loop {
+            tokio::select! {
+                item = rx.recv(), if items_open => match item {
+                    Some(Item { seq, value }) => {
+                        m.items_in.fetch_add(1, Ordering::Relaxed);
+                        match sink(value).await {
+                            Ok(()) => {
+                                m.items_out.fetch_add(1, Ordering::Relaxed);
+                            }
+                            Err(error) => {
+                                m.errors.fetch_add(1, Ordering::Relaxed);
+                                dead_letter(DeadLetter { stage: m.name.clone(), seq, error });
+                            }
+                        }
+                    }
+                    None => items_open = false,
+                },
+                letter = dead_rx.recv(), if dead_open => match letter {
+                    Some(letter) => dead_letter(letter),
+                    None => dead_open = false,
+                },
+                else => break,
+            }
+        }
+        m.finish();
+        while tasks.join_next().await.is_some() {}
+
+        metrics.push(m);
+        metrics.iter().map(|m| m.snapshot()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opts(parallelism: usize, ordered: bool) -> StageOptions {
+        StageOptions {
+            parallelism,
+            ordered,
+            buffer: 2,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keeps_order_and_routes_errors() {
+        let out = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink_out = out.clone();
+        let mut dead = Vec::new();
+
+        let stats = Pipeline::from_iter(1..=20u64, 2)
+            // Later items finish first, so only `ordered` keeps them in line
+            .stage("slow", opts(8, true), |n| async move {
+                tokio::time::sleep(Duration::from_millis(100 - n)).await;
+                Ok(n)
+            })
+            .stage("check", opts(2, true), |n| async move {
+                if n % 5 == 0 {
+                    anyhow::bail!("{n} is a multiple of 5");
+                }
+                if n == 7 {
+                    panic!("seven");
+                }
+                Ok(n * 10)
+            })
+            .run(
+                move |n| {
+                    let out = sink_out.clone();
+                    async move {
+                        anyhow::ensure!(n != 190, "sink refuses 190");
+                        out.lock().unwrap().push(n);
+                        Ok(())
+                    }
+                },
+                |d| dead.push((d.stage, d.seq)),
+            )
+            .await;
+
+        let expect: Vec<u64> = (1..=20)
+            .filter(|n| n % 5 != 0 && *n != 7 && *n != 19)
+            .map(|n| n * 10)
+            .collect();
+        assert_eq!(*out.lock().unwrap(), expect);
+
+        dead.sort_by_key(|(_, seq)| *seq);
+        let dead_seqs: Vec<(&str, u64)> = dead.iter().map(|(s, q)| (s.as_str(), *q)).collect();
+        assert_eq!(
+            dead_seqs,
+            [
+                ("check", 4),
+                ("check", 6),
+                ("check", 9),
+                ("check", 14),
+                ("sink", 18),
+                ("check", 19)
+            ]
+        );
+
+        let names: Vec<&str> = stats.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["source", "slow", "check", "sink"]);
+        assert_eq!(stats[1].items_out, 20);
+        assert_eq!((stats[2].items_in, stats[2].errors), (20, 5));
+        assert_eq!((stats[3].items_out, stats[3].errors), (14, 1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bounded_channels_push_back_on_the_source() {
+        let pulled = Arc::new(AtomicU64::new(0));
+        let counter = pulled.clone();
+        let source = (0..1000u64).inspect(move |_| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+        let pipeline =
+            Pipeline::from_iter(source, 2).stage("pass", opts(1, false), |n| async move { Ok(n) });
+
+        // Nobody drains the sink yet: only a few channel slots fill up
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(pulled.load(Ordering::Relaxed) < 10);
+
+        let stats = pipeline.run(|_| async { Ok(()) }, |_| {}).await;
+        assert_eq!(stats.last().unwrap().items_out, 1000);
+    }
+}
@@ -0,0 +1,392 @@
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::StreamExt;
+use rand::Rng;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, StatusCode};
+use tokio::time::{Instant, sleep, timeout};
+
+use crate::limit::HostLimiter;
+
+// Longest Retry-After we take from a server; anything above is clamped
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+
+// Why a fetch failed, by what the caller can do about it
+#[derive(Debug)]
+pub enum FetchError {
+    // DNS, TCP or TLS failed before we got a response
+    Connect(reqwest::Error),
+    // An attempt or the whole time budget ran out
+    Timeout,
+    // The response started but the body broke off
+    Body(reqwest::Error),
+    // 5xx
+    Server(StatusCode),
+    // 429, with the server's Retry-After if it sent one
+    RateLimited(Option<Duration>),
+    // Any other non-2xx: retrying won't change the answer
+    Client(StatusCode),
+    // Anything reqwest reports that isn't one of the above (bad URL, ...)
+    Request(reqwest::Error),
+}
+
+impl FetchError {
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            FetchError::Connect(_)
+                | FetchError::Timeout
+                | FetchError::Body(_)
+                | FetchError::Server(_)
+                | FetchError::RateLimited(_)
+        )
+    }
+
+    pub fn from_reqwest(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            FetchError::Timeout
+        } else if e.is_connect() {
+            FetchError::Connect(e)
+        } else if e.is_body() || e.is_decode() {
+            FetchError::Body(e)
+        } else {
+            FetchError::Request(e)
+        }
+    }
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FetchError::Connect(e) => write!(f, "connect failed: {e}"),
+            FetchError::Timeout => f.write_str("timeout"),
+            FetchError::Body(e) => write!(f, "body read failed: {e}"),
+            FetchError::Server(s) => write!(f, "server error: HTTP {s}"),
+            FetchError::RateLimited(Some(d)) => {
+                write!(f, "rate limited (retry after {}s)", d.as_secs())
+            }
+            FetchError::RateLimited(None) => f.write_str("rate limited"),
+            FetchError::Client(s) => write!(f, "HTTP {s}"),
+            FetchError::Request(e) => write!(f, "request failed: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for FetchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FetchError::Connect(e) | FetchError::Body(e) | FetchError::Request(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    // Total attempts, the first one included
+    pub max_attempts: u32,
+    // Backoff before retry n is random in [0, min(max_delay, base_delay * 2^n)]
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    // Cap for a single attempt
+    pub attempt_timeout: Duration,
+    // Cap for all attempts and sleeps together
+    pub budget: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(5),
+            attempt_timeout: Duration::from_secs(10),
+            budget: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    // "Full jitter" exponential backoff for the given retry (0-based)
+    fn backoff(&self, retry: u32) -> Duration {
+        let cap = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_delay);
+        cap.mul_f64(rand::rng().random::<f64>())
+    }
+}
+
+// One attempt: status is checked before the body, which is only counted
+async fn attempt(client: &Client, url: &str) -> Result<usize, FetchError> {
+    let resp = client
+        .get(url)
+        .send()
+        .await
+        .map_err(FetchError::from_reqwest)?;
+
+    let status = resp.status();
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        let retry_after = resp
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_retry_after);
+        return Err(FetchError::RateLimited(retry_after));
+    }
+    if status.is_server_error() {
+        return Err(FetchError::Server(status));
+    }
+    if !status.is_success() {
+        return Err(FetchError::Client(status));
+    }
+
+    let mut len = 0;
+    let mut body = resp.bytes_stream();
+    while let Some(chunk) = body.next().await {
+        len += chunk.map_err(FetchError::Body)?.len();
+    }
+    Ok(len)
+}
+
+// Fetch with retries for transient failures only. Gives up early when the
+// next wait would not fit in the remaining budget, returning the last error.
+// Every attempt goes through `limiter`, so retries count against the host's
+// rate and concurrency like any other request.
+pub async fn fetch_with_retry(
+    client: &Client,
+    url: &str,
+    policy: &RetryPolicy,
+    limiter: Option<&HostLimiter>,
+) -> Result<usize, FetchError> {
+    let deadline = Instant::now() + policy.budget;
+    let mut retry = 0;
+    loop {
+        let permit = match limiter {
+            Some(l) => Some(l.acquire(url).await),
+            None => None,
+        };
+        let left = deadline.saturating_duration_since(Instant::now());
+        let res = match timeout(policy.attempt_timeout.min(left), attempt(client, url)).await {
+            Ok(res) => res,
+            Err(_) => Err(FetchError::Timeout),
+        };
+        drop(permit);
+        let err = match res {
+            Ok(len) => return Ok(len),
+            Err(e) => e,
+        };
+        if !err.is_retryable() || retry + 1 >= policy.max_attempts {
+            return Err(err);
+        }
+
+        // Retry-After wins over our own backoff when the server sends one
+        let wait = match &err {
+            FetchError::RateLimited(Some(d)) => *d,
+            _ => policy.backoff(retry),
+        };
+        if wait >= deadline.saturating_duration_since(Instant::now()) {
+            return Err(err);
+        }
+        sleep(wait).await;
+        retry += 1;
+    }
+}
+
+// `Retry-After` is either delay-seconds or an IMF-fixdate
+// (`Sun, 06 Nov 1994 08:49:37 GMT`); a date in the past means "now".
+// Capped at `MAX_RETRY_AFTER`.
+fn parse_retry_after(v: &str) -> Option<Duration> {
+    let v = v.trim();
+    let secs = match v.parse::<u64>() {
+        Ok(secs) => secs,
+        Err(_) => {
+            let at = parse_imf_fixdate(v)?;
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+            at.saturating_sub(now)
+        }
+    };
+    Some(Duration::from_secs(secs).min(MAX_RETRY_AFTER))
+}
+
+// Seconds since the epoch for an IMF-fixdate
+fn parse_imf_fixdate(v: &str) -> Option<u64> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let mut parts = v.split_whitespace().skip(1);
+    let day: u64 = parts.next()?.parse().ok()?;
+    let mon = parts.next()?;
+    let month = MONTHS.iter().position(|m| *m == mon)? as u64 + 1;
+    let year: u64 = parts.next()?.parse().ok()?;
+    let mut hms = parts.next()?.split(':').map(|x| x.parse::<u64>().ok());
+    let (h, m, s) = (hms.next()??, hms.next()??, hms.next()??);
+    if parts.next()? != "GMT"
+        || !(1..=31).contains(&day)
+        || !(1..=9999).contains(&year)
+        || h > 23
+        || m > 59
+        || s > 60
+    {
+        return None;
+    }
+
+    // Days from civil date (proleptic Gregorian), shifted so March is month 0
+    let (y, mo) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = y / 400;
+    let yoe = y % 400;
+    let doy = (153 * mo + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = (era * 146_097 + doe).checked_sub(719_468)?;
+    Some(days * 86_400 + h * 3600 + m * 60 + s)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::limit::HostLimits;
+    use crate::test_server::{Route, TestServer};
+
+    fn quick() -> RetryPolicy {
+        RetryPolicy {
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(5),
+            ..RetryPolicy::default()
+        }
+    }
+
+    fn status(code: u16) -> Route {
+        Route {
+            status: code,
+            ..Route::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_5xx_until_success() {
+        let srv = TestServer::start(HashMap::new()).await;
+        srv.set_sequence("/", vec![status(503), status(502), Route::ok("hello")]);
+        let len = fetch_with_retry(&Client::new(), &srv.url("/"), &quick(), None)
+            .await
+            .unwrap();
+        assert_eq!(len, 5);
+        assert_eq!(srv.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn every_attempt_waits_for_the_host_limiter() {
+        let srv = TestServer::start(HashMap::new()).await;
+        srv.set_sequence("/", vec![status(503), status(502), Route::ok("hello")]);
+        let limiter = HostLimiter::new(HostLimits {
+            per_second: Some(10.0),
+            max_concurrent: Some(1),
+        });
+        let start = Instant::now();
+        let len = fetch_with_retry(&Client::new(), &srv.url("/"), &quick(), Some(&limiter))
+            .await
+            .unwrap();
+        assert_eq!(len, 5);
+        // Three starts 100ms apart, though the backoff alone is a few ms
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn permanent_4xx_is_not_retried() {
+        let srv = TestServer::start(HashMap::from([("/".to_string(), status(404))])).await;
+        let err = fetch_with_retry(&Client::new(), &srv.url("/"), &quick(), None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, FetchError::Client(StatusCode::NOT_FOUND)));
+        assert_eq!(srv.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn honours_retry_after_and_gives_up_past_budget() {
+        let limited = |secs: &str| Route {
+            headers: vec![("Retry-After".into(), secs.into())],
+            ..status(429)
+        };
+        let srv = TestServer::start(HashMap::new()).await;
+        srv.set_sequence("/", vec![limited("0"), Route::ok("x")]);
+        assert!(
+            fetch_with_retry(&Client::new(), &srv.url("/"), &quick(), None)
+                .await
+                .is_ok()
+        );
+
+        // Waiting 60s would blow the 30s budget, so stop right away
+        srv.set_route("/", limited("60"));
+        let err = fetch_with_retry(&Client::new(), &srv.url("/"), &quick(), None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, FetchError::RateLimited(Some(d)) if d.as_secs() == 60));
+
+        // An absurd Retry-After is clamped, not added to the clock
+        srv.set_route("/", limited("18446744073709551615"));
+        let err = fetch_with_retry(&Client::new(), &srv.url("/"), &quick(), None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, FetchError::RateLimited(Some(d)) if d == MAX_RETRY_AFTER));
+    }
+
+    #[tokio::test]
+    async fn connect_errors_are_classified() {
+        // Bind and drop to get a port nobody listens on
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let policy = RetryPolicy {
+            max_attempts: 2,
+            ..quick()
+        };
+        let err = fetch_with_retry(
+            &Client::new(),
+            &format!("http://127.0.0.1:{port}/"),
+            &policy,
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, FetchError::Connect(_)));
+    }
+
+    #[test]
+    fn parses_http_dates() {
+        assert_eq!(
+            parse_imf_fixdate("Sun, 06 Nov 1994 08:49:37 GMT"),
+            Some(784_111_777)
+        );
+        assert_eq!(
+            parse_retry_after("Sun, 06 Nov 1994 08:49:37 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse_retry_after("18446744073709551615"),
+            Some(MAX_RETRY_AFTER)
+        );
+        assert_eq!(
+            parse_retry_after("Fri, 31 Dec 9999 23:59:59 GMT"),
+            Some(MAX_RETRY_AFTER)
+        );
+        // Out-of-range fields are rejected instead of overflowing
+        assert_eq!(parse_imf_fixdate("Sat, 01 Jan 0 00:00:00 GMT"), None);
+        assert_eq!(
+            parse_imf_fixdate("Sun, 06 Nov 99999999999999 08:49:37 GMT"),
+            None
+        );
+        assert_eq!(
+            parse_imf_fixdate("Sun, 06 Nov 1994 99999999999999:49:37 GMT"),
+            None
+        );
+    }
+}
@@ -0,0 +1,163 @@
+// Just enough robots.txt: groups of `User-agent` lines followed by `Allow` /
+// `Disallow` rules, with `*` wildcards and a trailing `$` anchor. The most
+// specific (longest) matching rule wins; on a tie `Allow` wins.
+
+#[derive(Clone, Debug, Default)]
+pub struct Robots {
+    // (allow, pattern) for the group that applies to us
+    rules: Vec<(bool, String)>,
+}
+
+impl Robots {
+    // Everything allowed (no robots.txt, or it couldn't be fetched)
+    pub fn allow_all() -> Self {
+        Self::default()
+    }
+
+    // Keep the rules of the group naming our agent, falling back to `*`
+    pub fn parse(text: &str, user_agent: &str) -> Self {
+        let agent = user_agent
+            .split('/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+
+        let mut ours = None;
+        let mut star = None;
+        // Agents of the group being read, and whether we've hit its rules yet
+        let mut agents: Vec<String> = Vec::new();
+        let mut in_rules = false;
+        let mut rules = Vec::new();
+        let mut flush = |agents: &[String], rules: Vec<(bool, String)>| {
+            if agents
+                .iter()
+                .any(|a| !a.is_empty() && agent.contains(a.as_str()))
+            {
+                ours.get_or_insert(rules);
+            } else if agents.iter().any(|a| a == "*") {
+                star.get_or_insert(rules);
+            }
+        };
+
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "user-agent" => {
+                    if in_rules {
+                        flush(&agents, std::mem::take(&mut rules));
+                        agents.clear();
+                        in_rules = false;
+                    }
+                    agents.push(value.to_ascii_lowercase());
+                }
+                "allow" | "disallow" => {
+                    in_rules = true;
+                    // An empty Disallow means "allow everything"
+                    if !value.is_empty() {
+                        rules.push((key.trim().eq_ignore_ascii_case("allow"), value.to_string()));
+                    }
+                }
+                _ => {}
+            }
+        }
+        flush(&agents, rules);
+
+        Self {
+            rules: ours.or(star).unwrap_or_default(),
+        }
+    }
+
+    // `path` includes the query string, as it appears in the request line
+    pub fn allowed(&self, path: &str) -> bool {
+        self.rules
+            .iter()
+            .filter(|(_, pattern)| matches(pattern.as_bytes(), path.as_bytes()))
+            .max_by_key(|(allow, pattern)| (pattern.len(), *allow))
+            .is_none_or(|(allow, _)| *allow)
+    }
+}
+
+// Prefix match where `*` matches any run of bytes and a final `$` anchors
+// the pattern to the end of the path. Two pointers with backtracking to the
+// last `*` only, so it stays O(pattern * path) however many `*`s there are.
+fn matches(pattern: &[u8], path: &[u8]) -> bool {
+    let (pattern, anchored) = match pattern.split_last() {
+        Some((b'$', rest)) => (rest, true),
+        _ => (pattern, false),
+    };
+    let (mut p, mut s) = (0, 0);
+    // Position just after the last `*`, and where in the path its run ends
+    let mut star = None;
+    loop {
+        if p == pattern.len() {
+            if !anchored || s == path.len() {
+                return true;
+            }
+        } else if pattern[p] == b'*' {
+            p += 1;
+            star = Some((p, s));
+            continue;
+        } else if path.get(s) == Some(&pattern[p]) {
+            p += 1;
+            s += 1;
+            continue;
+        }
+        // Mismatch: let the last `*` swallow one more byte and retry
+        match star {
+            Some((after, end)) if end < path.len() => {
+                star = Some((after, end + 1));
+                p = after;
+                s = end + 1;
+            }
+            _ => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_our_group_and_longest_rule() {
+        let txt = "\
+User-agent: *
+Disallow: /
+
+User-agent: other-bot
+User-agent: lesson05
+Disallow: /private
+Allow: /private/ok
+Disallow: /*.pdf$
+";
+        let r = Robots::parse(txt, "lesson05/0.1");
+        assert!(r.allowed("/"));
+        assert!(!r.allowed("/private/x"));
+        assert!(r.allowed("/private/ok/1"));
+        assert!(!r.allowed("/docs/a.pdf"));
+        assert!(r.allowed("/docs/a.pdf?x=1"));
+
+        let other = Robots::parse(txt, "curl/8");
+        assert!(!other.allowed("/anything"));
+    }
+
+    #[test]
+    fn wildcards_match_without_backtracking_blowup() {
+        assert!(matches(b"/*.pdf$", b"/a/b.pdf"));
+        assert!(!matches(b"/*.pdf$", b"/a/b.pdf.bak"));
+        assert!(matches(b"/a*b*c", b"/axxbyyczz"));
+        assert!(matches(b"/*", b"/"));
+        assert!(matches(b"$", b""));
+        assert!(!matches(b"/a$", b"/ab"));
+
+        let path = format!("/{}", "a".repeat(10_000));
+        let started = std::time::Instant::now();
+        assert!(!matches(b"/*a*a*a*a*a*a*a*b", path.as_bytes()));
+        assert!(matches(b"/*a*a*a*a*a*a*a$", path.as_bytes()));
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::Duration;
+
+use anyhow::Result;
+use tokio::task::{Id, JoinError, JoinHandle, JoinSet};
+use tokio_util::sync::CancellationToken;
+
+// What to do when a task fails (returns Err or panics)
+#[derive(Clone, Copy, Debug)]
+pub enum Restart {
+    Never,
+    // Start it again, at most `max_restarts` times
+    OnFailure { max_restarts: u32 },
+}
+
+// How a supervised task ended up
+#[derive(Debug)]
+pub enum Exit {
+    Ok,
+    Failed(anyhow::Error),
+    Panicked(String),
+    // Stopped because its token was cancelled
+    Cancelled,
+    // Still running when the shutdown deadline passed
+    Aborted,
+}
+
+#[derive(Debug)]
+pub struct TaskReport {
+    pub name: String,
+    pub exit: Exit,
+    pub restarts: u32,
+}
+
+// Aborts the wrapped task when dropped, so aborting a supervision loop also
+// stops the attempt it was waiting on
+struct AbortOnDrop<T>(JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+// A group of named tasks sharing one cancellation token. Child supervisors
+// get a child token: cancelling them leaves the parent running, cancelling
+// the parent stops every subtree.
+pub struct Supervisor {
+    token: CancellationToken,
+    tasks: JoinSet<TaskReport>,
+    names: HashMap<Id, String>,
+    reports: Vec<TaskReport>,
+    // Cancel the whole group once any task fails for good
+    fail_fast: bool,
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self::with_token(CancellationToken::new())
+    }
+}
+
+impl Supervisor {
+    fn with_token(token: CancellationToken) -> Self {
+        Self {
+            token,
+            tasks: JoinSet::new(),
+            names: HashMap::new(),
+            reports: Vec::new(),
+            fail_fast: false,
+        }
+    }
+
+    pub fn fail_fast(mut self) -> Self {
+        self.fail_fast = true;
+        self
+    }
+
+    pub fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    // A subtree: cancelled along with us, but can be cancelled on its own
+    pub fn child(&self) -> Supervisor {
+        Self::with_token(self.token.child_token())
+    }
+
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    // Run `make(token)` as task `name`, restarting it per `restart`. The
+    // factory is called again for every restart, so it must be `Fn`.
+    pub fn spawn<F, Fut>(&mut self, name: &str, restart: Restart, make: F)
+    where
+        F: Fn(CancellationToken) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let token = self.token.clone();
+        let fail_fast = self.fail_fast;
+        let task_name = name.to_string();
+        let handle = self.tasks.spawn(async move {
+            let mut restarts = 0;
+            loop {
+                // A separate task per attempt turns panics into JoinErrors
+                let mut attempt = AbortOnDrop(tokio::spawn(make(token.clone())));
+                let exit = match (&mut attempt.0).await {
+                    Ok(Ok(())) if token.is_cancelled() => Exit::Cancelled,
+                    Ok(Ok(())) => Exit::Ok,
+                    Ok(Err(e)) => Exit::Failed(e),
+                    Err(e) => join_exit(e),
+                };
+                let failed = matches!(exit, Exit::Failed(_) | Exit::Panicked(_));
+                match restart {
+                    Restart::OnFailure { max_restarts }
+                        if failed && restarts < max_restarts && !token.is_cancelled() =>
+                    {
+                        eprintln!("[supervisor] {task_name} failed ({exit:?}), restarting");
+                        restarts += 1;
+                    }
+                    _ => {
+                        if failed && fail_fast {
+                            token.cancel();
+                        }
+                        return TaskReport {
+                            name: task_name,
+                            exit,
+                            restarts,
+                        };
+                    }
+                }
+            }
+        });
+        self.names.insert(handle.id(), name.to_string());
+    }
+
+    fn record(&mut self, res: Result<TaskReport, JoinError>) {
+        let report = match res {
+            Ok(report) => report,
+            Err(e) => TaskReport {
+                name: self.names.get(&e.id()).cloned().unwrap_or_default(),
+                exit: if e.is_cancelled() {
+                    Exit::Aborted
+                } else {
+                    join_exit(e)
+                },
+                restarts: 0,
+            },
+        };
+        self.reports.push(report);
+    }
+
+    // Wait for every task to finish on its own. Cancel-safe: reports that
+    // came in before the future was dropped are kept.
+    pub async fn wait(&mut self) {
+        while let Some(res) = self.tasks.join_next().await {
+            self.record(res);
+        }
+    }
+
+    // Cancel everything, give tasks `deadline` to wind down, abort the rest
+    pub async fn shutdown(mut self, deadline: Duration) -> Vec<TaskReport> {
+        self.cancel();
+        if tokio::time::timeout(deadline, self.wait()).await.is_err() {
+            self.tasks.abort_all();
+            self.wait().await;
+        }
+        self.reports
+    }
+
+    // Run until every task is done, or until Ctrl-C triggers a shutdown
+    pub async fn run_until_ctrl_c(mut self, deadline: Duration) -> Vec<TaskReport> {
+        tokio::select! {
+            _ = self.wait() => self.reports,
+            _ = tokio::signal::ctrl_c() => {
+                eprintln!("[supervisor] Ctrl-C, shutting down (deadline {deadline:?})");
+                self.shutdown(deadline).await
+            }
+        }
+    }
+}
+
+fn join_exit(e: JoinError) -> Exit {
+    if !e.is_panic() {
+        return Exit::Cancelled;
+    }
+    let payload = e.into_panic();
+    let msg = payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "non-string panic payload".to_string());
+    Exit::Panicked(msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use tokio::time::sleep;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn panics_become_reports_and_restarts_stop_at_limit() {
+        let attempts = Arc::new(AtomicU32::new(0));
+        let mut sup = Supervisor::default();
+        let seen = attempts.clone();
+        sup.spawn("flaky", Restart::OnFailure { max_restarts: 2 }, move |_| {
+            let seen = seen.clone();
+            async move {
+                seen.fetch_add(1, Ordering::SeqCst);
+                panic!("boom");
+            }
+        });
+        sup.spawn("fine", Restart::Never, |_| async { Ok(()) });
+
+        sup.wait().await;
+        let mut reports = sup.reports;
+        reports.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        assert!(matches!(&reports[1].exit, Exit::Panicked(m) if m == "boom"));
+        assert_eq!(reports[1].restarts, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancelling_a_child_leaves_the_parent_running() {
+        let mut parent = Supervisor::default();
+        let mut child = parent.child();
+        let sleepy = |token: CancellationToken| async move {
+            tokio::select! {
+                _ = sleep(Duration::from_secs(60)) => Ok(()),
+                _ = token.cancelled() => Ok(()),
+            }
+        };
+        parent.spawn("parent", Restart::Never, sleepy);
+        child.spawn("child", Restart::Never, sleepy);
+
+        child.cancel();
+        child.wait().await;
+        assert!(matches!(child.reports[0].exit, Exit::Cancelled));
+        assert!(!parent.token().is_cancelled());
+
+        // The parent task runs to completion on its own
+        parent.wait().await;
+        assert!(matches!(parent.reports[0].exit, Exit::Ok));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fail_fast_cancels_siblings_and_deadline_aborts_stragglers() {
+        let mut sup = Supervisor::default().fail_fast();
+        sup.spawn("bad", Restart::Never, |_| async {
+            sleep(Duration::from_millis(10)).await;
+            anyhow::bail!("nope")
+        });
+        sup.spawn("polite", Restart::Never, |token| async move {
+            token.cancelled().await;
+            Ok(())
+        });
+        sup.wait().await;
+        assert_eq!(sup.reports.len(), 2);
+        assert!(sup.token().is_cancelled());
+
+        // Ignores its token, so only the deadline stops it
+        let mut sup = Supervisor::default();
+        sup.spawn("stubborn", Restart::Never, |_| async {
+            sleep(Duration::from_secs(3600)).await;
+            Ok(())
+        });
+        let reports = sup.shutdown(Duration::from_secs(1)).await;
+        assert_eq!(reports[0].name, "stubborn");
+        assert!(matches!(reports[0].exit, Exit::Aborted));
+    }
+}
@@ -0,0 +1,226 @@
+// Tiny HTTP/1.1 server for tests: serves canned responses per path and
+// logs every request it sees. Just enough HTTP for reqwest, nothing more.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+#[derive(Clone, Default)]
+pub struct Route {
+    pub status: u16,
+    pub body: Vec<u8>,
+    pub headers: Vec<(String, String)>,
+    // Honour `Range: bytes=a-b` and advertise `Accept-Ranges: bytes`
+    pub ranges: bool,
+    // Close the connection after this many body bytes (Content-Length still
+    // announces the full size, so the client sees a truncated body)
+    pub cut_after: Option<usize>,
+    // Pause this long between the head and the body
+    pub stall: Option<Duration>,
+    // Pause this long before answering at all
+    pub delay: Option<Duration>,
+    // End a cut body with a TCP reset instead of a clean close
+    pub reset: bool,
+    // Send the body as one chunk with no Content-Length
+    pub chunked: bool,
+}
+
+impl Route {
+    pub fn ok(body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status: 200,
+            body: body.into(),
+            ..Self::default()
+        }
+    }
+}
+
+// Each path serves the front of its queue; all but the last entry are
+// used up one request at a time
+type Routes = Arc<Mutex<HashMap<String, Vec<Route>>>>;
+
+pub struct TestServer {
+    pub addr: SocketAddr,
+    routes: Routes,
+    // "METHOD /path", plus " range=..." / " if-none-match=..." /
+    // " authorization=..." when those headers were sent
+    pub requests: Arc<Mutex<Vec<String>>>,
+    // Connections being served right now, and the most there ever were
+    open: Arc<(AtomicUsize, AtomicUsize)>,
+}
+
+impl TestServer {
+    pub async fn start(routes: HashMap<String, Route>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let routes = routes.into_iter().map(|(k, v)| (k, vec![v])).collect();
+        let routes = Arc::new(Mutex::new(routes));
+        let open = Arc::new((AtomicUsize::new(0), AtomicUsize::new(0)));
+        let (log, shared, counts) = (requests.clone(), routes.clone(), open.clone());
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (log, shared, counts) = (log.clone(), shared.clone(), counts.clone());
+                tokio::spawn(async move {
+                    let now = counts.0.fetch_add(1, Ordering::SeqCst) + 1;
+                    counts.1.fetch_max(now, Ordering::SeqCst);
+                    handle(stream, shared, log).await;
+                    counts.0.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+        Self {
+            addr,
+            routes,
+            requests,
+            open,
+        }
+    }
+
+    // Replace (or add) what a path serves from now on
+    pub fn set_route(&self, path: &str, route: Route) {
+        self.set_sequence(path, vec![route]);
+    }
+
+    // Serve these in order, then keep serving the last one
+    pub fn set_sequence(&self, path: &str, routes: Vec<Route>) {
+        self.routes.lock().unwrap().insert(path.to_string(), routes);
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{path}", self.addr)
+    }
+
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    // Most requests this server was ever serving at the same time
+    pub fn peak_concurrency(&self) -> usize {
+        self.open.1.load(Ordering::SeqCst)
+    }
+}
+
+async fn handle(mut stream: TcpStream, routes: Routes, log: Arc<Mutex<Vec<String>>>) {
+    // Read just the request head; test requests never carry a body
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+        }
+    }
+    let head = String::from_utf8_lossy(&buf).to_string();
+    let mut lines = head.lines();
+    let mut first = lines.next().unwrap_or_default().split_whitespace();
+    let method = first.next().unwrap_or_default().to_string();
+    let path = first.next().unwrap_or_default().to_string();
+    let headers: Vec<(&str, &str)> = lines
+        .filter_map(|l| l.split_once(':'))
+        .map(|(k, v)| (k.trim(), v.trim()))
+        .collect();
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.to_string())
+    };
+    let range = header("range");
+
+    let mut entry = format!("{method} {path}");
+    if let Some(r) = &range {
+        entry.push_str(&format!(" range={r}"));
+    }
+    if let Some(tag) = header("if-none-match") {
+        entry.push_str(&format!(" if-none-match={tag}"));
+    }
+    if let Some(auth) = header("authorization") {
+        entry.push_str(&format!(" authorization={auth}"));
+    }
+    log.lock().unwrap().push(entry);
+
+    let route = match routes.lock().unwrap().get_mut(&path) {
+        Some(queue) if queue.len() > 1 => Some(queue.remove(0)),
+        Some(queue) => queue.first().cloned(),
+        None => None,
+    };
+    let Some(route) = route else {
+        let _ = stream
+            .write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+            .await;
+        return;
+    };
+
+    if let Some(d) = route.delay {
+        tokio::time::sleep(d).await;
+    }
+    let total = route.body.len();
+    let mut status = route.status;
+    let mut body = &route.body[..];
+    let mut extra = route.headers.clone();
+    if route.ranges {
+        extra.push(("Accept-Ranges".into(), "bytes".into()));
+        if let Some((start, end)) = range.as_deref().and_then(|r| parse_range(r, total)) {
+            status = 206;
+            body = &route.body[start..=end];
+            extra.push((
+                "Content-Range".into(),
+                format!("bytes {start}-{end}/{total}"),
+            ));
+        }
+    }
+
+    let mut resp = format!("HTTP/1.1 {status} X\r\nConnection: close\r\n");
+    if route.chunked {
+        resp.push_str("Transfer-Encoding: chunked\r\n");
+    } else {
+        resp.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    for (k, v) in &extra {
+        resp.push_str(&format!("{k}: {v}\r\n"));
+    }
+    resp.push_str("\r\n");
+    if stream.write_all(resp.as_bytes()).await.is_err() || method == "HEAD" {
+        return;
+    }
+    if let Some(d) = route.stall {
+        tokio::time::sleep(d).await;
+    }
+    let body = match route.cut_after {
+        Some(n) => &body[..n.min(body.len())],
+        None => body,
+    };
+    if route.chunked {
+        let framed = [
+            format!("{:x}\r\n", body.len()).as_bytes(),
+            body,
+            b"\r\n0\r\n\r\n",
+        ]
+        .concat();
+        let _ = stream.write_all(&framed).await;
+    } else {
+        let _ = stream.write_all(body).await;
+    }
+    if route.reset {
+        // Zero linger turns the close into an RST
+        let _ = stream.set_linger(Some(Duration::ZERO));
+    }
+}
+
+// `bytes=a-b` or `bytes=a-`, clamped to the body
+fn parse_range(r: &str, total: usize) -> Option<(usize, usize)> {
+    let (a, b) = r.strip_prefix("bytes=")?.split_once('-')?;
+    let start: usize = a.parse().ok()?;
+    let end = if b.is_empty() {
+        total.checked_sub(1)?
+    } else {
+        b.parse::<usize>().ok()?.min(total.checked_sub(1)?)
+    };
+    (start <= end).then_some((start, end))
+}
@@ -0,0 +1,250 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize, Serializer};
+use std::{env, ffi::OsString, fmt, fs, path::PathBuf, str::FromStr};
+
+// Where a setting's effective value came from
+#[derive(Clone, Debug, PartialEq)]
+pub enum Origin {
+    Default,
+    File(PathBuf),
+    Env(String),
+    Flag(&'static str),
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::Default => f.write_str("default"),
+            Origin::File(path) => write!(f, "{}", path.display()),
+            Origin::Env(var) => write!(f, "env {var}"),
+            Origin::Flag(flag) => write!(f, "flag {flag}"),
+        }
+    }
+}
+
+// Written the same way `config show --origin` prints it
+impl Serialize for Origin {
+    fn serialize<S: Serializer>(&self, s: S) -> std::result::Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Setting<T> {
+    pub value: T,
+    pub origin: Origin,
+}
+
+// One source of settings; anything left out falls through to the layer below
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Layer {
+    pub len: Option<usize>,
+    pub verbose: Option<u8>,
+}
+
+// The merged settings
+#[derive(Clone, Debug, Serialize)]
+pub struct Config {
+    pub len: Setting<usize>,
+    pub verbose: Setting<u8>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            len: Setting {
+                value: 16,
+                origin: Origin::Default,
+            },
+            verbose: Setting {
+                value: 0,
+                origin: Origin::Default,
+            },
+        }
+    }
+}
+
+impl Config {
+    // Lay `layer` over what we have so far
+    fn apply(&mut self, layer: Layer, origin: impl Fn(&'static str) -> Origin) {
+        if let Some(v) = layer.len {
+            self.len = Setting {
+                value: v,
+                origin: origin("len"),
+            };
+        }
+        if let Some(v) = layer.verbose {
+            self.verbose = Setting {
+                value: v,
+                origin: origin("verbose"),
+            };
+        }
+    }
+
+    // Defaults < system < user < project < MYCLI_* < `flags`
+    pub fn load(flags: Layer) -> Result<Self> {
+        let mut config = Config::default();
+        for path in config_files() {
+            if let Some(layer) = read_file(&path)? {
+                config.apply(layer, |_| Origin::File(path.clone()));
+            }
+        }
+        config.apply(env_layer(env::vars_os())?, env_origin);
+        config.apply(flags, |key| match key {
+            "len" => Origin::Flag("--len"),
+            _ => Origin::Flag("--verbose"),
+        });
+        Ok(config)
+    }
+
+    // `key = value` lines, optionally with where each value came from
+    pub fn show(&self, origin: bool) -> String {
+        let rows = [
+            ("len", self.len.value.to_string(), &self.len.origin),
+            (
+                "verbose",
+                self.verbose.value.to_string(),
+                &self.verbose.origin,
+            ),
+        ];
+        rows.iter()
+            .map(|(key, value, from)| {
+                if origin {
+                    format!("{:<20} # {from}\n", format!("{key} = {value}"))
+                } else {
+                    format!("{key} = {value}\n")
+                }
+            })
+            .collect()
+    }
+}
+
+// Candidate files, lowest precedence first; missing ones are skipped
+fn config_files() -> Vec<PathBuf> {
+    let mut files = vec![PathBuf::from("/etc/mycli/config.toml")];
+    let user_dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+    if let Some(dir) = user_dir {
+        files.push(dir.join("mycli").join("config.toml"));
+    }
+    files.push(PathBuf::from("mycli.toml"));
+    files
+}
+
+fn read_file(path: &PathBuf) -> Result<Option<Layer>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+    };
+    let layer =
+        toml::from_str(&text).with_context(|| format!("bad config in {}", path.display()))?;
+    Ok(Some(layer))
+}
+
+fn env_origin(key: &'static str) -> Origin {
+    Origin::Env(format!("MYCLI_{}", key.to_uppercase()))
+}
+
+// `MYCLI_LEN`, `MYCLI_VERBOSE`; other variables are ignored, even ones that
+// aren't UTF-8. A MYCLI_* value that isn't UTF-8 is an error.
+fn env_layer(vars: impl Iterator<Item = (OsString, OsString)>) -> Result<Layer> {
+    fn parse<T: FromStr>(var: &str, value: &str) -> Result<T>
+    where
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        value
+            .trim()
+            .parse()
+            .with_context(|| format!("bad value in {var}: `{value}`"))
+    }
+
+    let mut layer = Layer::default();
+    for (var, value) in vars {
+        let Some(var) = var.to_str().filter(|v| v.starts_with("MYCLI_")) else {
+            continue;
+        };
+        let Some(value) = value.to_str() else {
+            anyhow::bail!("{var} is not valid UTF-8: {value:?}");
+        };
+        match var {
+            "MYCLI_LEN" => layer.len = Some(parse(var, value)?),
+            "MYCLI_VERBOSE" => layer.verbose = Some(parse(var, value)?),
+            _ => {}
+        }
+    }
+    Ok(layer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> impl Iterator<Item = (OsString, OsString)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.into(), v.into()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn later_layers_win_and_remember_their_origin() {
+        let mut config = Config::default();
+        let user: Layer = toml::from_str("len = 24\nverbose = 1").unwrap();
+        config.apply(user, |_| Origin::File("user.toml".into()));
+        let project: Layer = toml::from_str("len = 32").unwrap();
+        config.apply(project, |_| Origin::File("mycli.toml".into()));
+        config.apply(
+            env_layer(vars(&[("MYCLI_VERBOSE", "2")])).unwrap(),
+            env_origin,
+        );
+
+        assert_eq!(config.len.value, 32);
+        assert_eq!(config.len.origin, Origin::File("mycli.toml".into()));
+        assert_eq!(config.verbose.value, 2);
+        assert_eq!(config.verbose.origin.to_string(), "env MYCLI_VERBOSE");
+
+        config.apply(
+            Layer {
+                len: Some(8),
+                verbose: None,
+            },
+            |_| Origin::Flag("--len"),
+        );
+        assert_eq!(
+            config.show(true),
+            "len = 8              # flag --len\nverbose = 2          # env MYCLI_VERBOSE\n"
+        );
+        assert_eq!(config.show(false), "len = 8\nverbose = 2\n");
+    }
+
+    #[test]
+    fn bad_values_are_errors() {
+        let err = env_layer(vars(&[("MYCLI_LEN", "many")])).unwrap_err();
+        assert_eq!(err.to_string(), "bad value in MYCLI_LEN: `many`");
+        assert!(toml::from_str::<Layer>("lenght = 3").is_err());
+        assert!(env_layer(vars(&[("PATH", "/bin")])).unwrap().len.is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_values_are_config_errors() {
+        use crate::output::{Code, WithCode};
+        use std::os::unix::ffi::OsStringExt;
+
+        let bad = || OsString::from_vec(b"\xff8".to_vec());
+        // Someone else's variable is none of our business
+        let other = env_layer([(bad(), bad()), ("OTHER".into(), bad())].into_iter());
+        assert!(other.unwrap().len.is_none());
+
+        let err = env_layer([("MYCLI_LEN".into(), bad())].into_iter())
+            .code(Code::Config)
+            .unwrap_err();
+        assert_eq!(err.to_string(), r#"MYCLI_LEN is not valid UTF-8: "\xFF8""#);
+        assert_eq!(Code::of(&err), Code::Config);
+    }
+}
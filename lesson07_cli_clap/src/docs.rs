@@ -0,0 +1,121 @@
+use anyhow::{Context, Result};
+use clap::Command;
+use clap_complete::Shell;
+use std::{fs, path::Path};
+
+// Completion script for `shell`, generated from the full command tree
+pub fn completions(shell: Shell, mut cmd: Command) -> String {
+    let name = cmd.get_name().to_string();
+    let mut out = Vec::new();
+    clap_complete::generate(shell, &mut cmd, name, &mut out);
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+// One roff page per command: `mycli.1`, `mycli-hello.1`, ... in `dir`.
+// Returns the files written.
+pub fn man_pages(cmd: Command, dir: &Path) -> Result<Vec<String>> {
+    fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    clap_mangen::generate_to(cmd, dir)
+        .with_context(|| format!("failed to write man pages to {}", dir.display()))?;
+    let mut written: Vec<String> = fs::read_dir(dir)?
+        .filter_map(|e| e.ok()?.file_name().into_string().ok())
+        .filter(|name| name.ends_with(".1"))
+        .collect();
+    written.sort();
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Cli;
+    use clap::CommandFactory;
+
+    // Temp dir removed when dropped, even if an assertion fails first
+    struct Scratch(std::path::PathBuf);
+
+    impl Scratch {
+        fn new(what: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("mycli-{what}-{}", std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn script(shell: Shell) -> String {
+        completions(shell, Cli::command())
+    }
+
+    #[test]
+    fn completion_scripts_are_valid() {
+        for shell in [
+            Shell::Bash,
+            Shell::Zsh,
+            Shell::Fish,
+            Shell::PowerShell,
+            Shell::Elvish,
+        ] {
+            let s = script(shell);
+            assert!(s.contains("mycli") && s.contains("hello"), "{shell}");
+        }
+
+        // `-n` only parses, it runs nothing. bash is always checked; zsh and
+        // fish only where they're installed
+        let dir = Scratch::new("completions");
+        for (shell, program, required) in [
+            (Shell::Bash, "bash", true),
+            (Shell::Zsh, "zsh", false),
+            (Shell::Fish, "fish", false),
+        ] {
+            let path = dir.0.join(format!("mycli.{program}"));
+            fs::write(&path, script(shell)).unwrap();
+            match std::process::Command::new(program)
+                .arg("-n")
+                .arg(&path)
+                .status()
+            {
+                Ok(status) => assert!(status.success(), "{program} rejected the completion script"),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => {
+                    eprintln!("{program} not installed, its completion script is unchecked")
+                }
+                Err(e) => panic!("couldn't run {program} to check the completion script: {e}"),
+            }
+        }
+    }
+
+    #[test]
+    fn man_pages_cover_every_subcommand() {
+        let scratch = Scratch::new("man");
+        let dir = scratch.0.join("man1");
+        let written = man_pages(Cli::command(), &dir).unwrap();
+        assert!(written.contains(&"mycli.1".to_string()));
+        for sub in Cli::command().get_subcommands() {
+            let page = format!("mycli-{}.1", sub.get_name());
+            assert!(written.contains(&page), "no {page}");
+        }
+
+        // Every page is well-formed roff: a title line first, then only
+        // requests or text, never a stray unescaped control character
+        for name in &written {
+            let roff = fs::read_to_string(dir.join(name)).unwrap();
+            let mut lines = roff
+                .lines()
+                .filter(|l| !l.starts_with(".ie") && !l.starts_with(".el"));
+            assert!(lines.next().unwrap().starts_with(".TH "), "{name}");
+            assert!(
+                roff.contains(".SH NAME") && roff.contains(".SH SYNOPSIS"),
+                "{name}"
+            );
+            assert!(
+                lines.all(|l| !l.starts_with('\'') && (!l.starts_with('.') || l.len() > 1)),
+                "{name}"
+            );
+        }
+    }
+}
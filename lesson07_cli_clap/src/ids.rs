@@ -0,0 +1,159 @@
+use clap::ValueEnum;
+use rand::rngs::{OsRng, StdRng};
+use rand::{Rng, RngCore, SeedableRng, TryRngCore};
+use std::time::{SystemTime, UNIX_EPOCH};
+use ulid::Ulid;
+use uuid::Builder;
+
+const BASE62: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+const HEX: &str = "0123456789abcdef";
+const CROCKFORD: &str = "0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+const URL_SAFE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+// Base62 minus 0/O/o and 1/I/l
+const NO_LOOKALIKES: &str = "23456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnpqrstuvwxyz";
+// Same alphabet and default size as the reference nanoid
+const NANOID: &str = "useandom-26T198340PX75pxJACKVERYMINDBUSHWOLF_GQZbfghjklqvwyzrict";
+pub const NANOID_LEN: usize = 21;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    #[default]
+    Base62,
+    Hex,
+    // Crockford base32: no I, L, O or U
+    Crockford,
+    // RFC 4648 base64url alphabet
+    UrlSafe,
+    NoLookalikes,
+    Nanoid,
+    Uuid4,
+    // Time-ordered UUID: millisecond timestamp, then random bits
+    Uuid7,
+    // 26 Crockford characters: millisecond timestamp, then 80 random bits
+    Ulid,
+}
+
+impl Format {
+    // The symbols a character is drawn from; `None` for fixed layouts
+    fn alphabet(self) -> Option<&'static str> {
+        match self {
+            Format::Base62 => Some(BASE62),
+            Format::Hex => Some(HEX),
+            Format::Crockford => Some(CROCKFORD),
+            Format::UrlSafe => Some(URL_SAFE),
+            Format::NoLookalikes => Some(NO_LOOKALIKES),
+            Format::Nanoid => Some(NANOID),
+            Format::Uuid4 | Format::Uuid7 | Format::Ulid => None,
+        }
+    }
+
+    // Random bits in one ID: `len` only matters for alphabet formats
+    pub fn entropy_bits(self, len: usize) -> f64 {
+        match (self, self.alphabet()) {
+            (_, Some(a)) => len as f64 * (a.len() as f64).log2(),
+            // 128 minus 4 version and 2 variant bits
+            (Format::Uuid4, _) => 122.0,
+            // 128 minus 48 timestamp, 4 version and 2 variant bits
+            (Format::Uuid7, _) => 74.0,
+            _ => 80.0,
+        }
+    }
+
+    pub fn has_length(self) -> bool {
+        self.alphabet().is_some()
+    }
+}
+
+// Where the randomness comes from
+#[derive(Clone, Copy, Debug, Default)]
+pub enum Source {
+    // The thread-local CSPRNG (seeded from the OS, reseeds itself)
+    #[default]
+    Thread,
+    // Every byte straight from the OS (getrandom)
+    Os,
+    // Deterministic, for tests and reproducible output; NOT secret
+    Seeded(u64),
+}
+
+impl Source {
+    pub fn rng(self) -> Box<dyn RngCore> {
+        match self {
+            Source::Thread => Box::new(rand::rng()),
+            Source::Os => Box::new(OsRng.unwrap_err()),
+            Source::Seeded(seed) => Box::new(StdRng::seed_from_u64(seed)),
+        }
+    }
+}
+
+// One ID. Time-based formats always embed the current time, so with a
+// seeded source only their random part repeats.
+pub fn generate(format: Format, len: usize, rng: &mut dyn RngCore) -> String {
+    if let Some(alphabet) = format.alphabet() {
+        let symbols = alphabet.as_bytes();
+        return (0..len)
+            .map(|_| symbols[rng.random_range(0..symbols.len())] as char)
+            .collect();
+    }
+    let mut bytes = [0u8; 16];
+    rng.fill_bytes(&mut bytes);
+    match format {
+        Format::Uuid4 => Builder::from_random_bytes(bytes).into_uuid().to_string(),
+        Format::Uuid7 => {
+            let tail: [u8; 10] = bytes[..10].try_into().unwrap();
+            Builder::from_unix_timestamp_millis(now_ms(), &tail)
+                .into_uuid()
+                .to_string()
+        }
+        _ => Ulid::from_parts(now_ms(), u128::from_le_bytes(bytes)).to_string(),
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn batch(format: Format, len: usize, seed: u64) -> Vec<String> {
+        let mut rng = Source::Seeded(seed).rng();
+        (0..5).map(|_| generate(format, len, &mut rng)).collect()
+    }
+
+    #[test]
+    fn ids_use_only_their_alphabet_and_seeds_repeat() {
+        for format in Format::value_variants().iter().filter(|f| f.has_length()) {
+            let alphabet = format.alphabet().unwrap();
+            for id in batch(*format, 40, 7) {
+                assert_eq!(id.len(), 40);
+                assert!(id.chars().all(|c| alphabet.contains(c)), "{format:?}: {id}");
+            }
+            assert_eq!(batch(*format, 40, 7), batch(*format, 40, 7));
+            assert_ne!(batch(*format, 40, 7), batch(*format, 40, 8));
+        }
+        assert!(!NO_LOOKALIKES.contains(['0', 'O', 'o', '1', 'I', 'l']));
+    }
+
+    #[test]
+    fn fixed_layouts_parse_back() {
+        for id in batch(Format::Uuid4, 0, 1) {
+            let uuid = uuid::Uuid::parse_str(&id).unwrap();
+            assert_eq!(uuid.get_version_num(), 4);
+        }
+        let v7 = batch(Format::Uuid7, 0, 1);
+        assert_eq!(uuid::Uuid::parse_str(&v7[0]).unwrap().get_version_num(), 7);
+        // Same millisecond or later: v7 and ULIDs sort by creation time
+        let ulids = batch(Format::Ulid, 0, 1);
+        let first = Ulid::from_string(&ulids[0]).unwrap();
+        assert_eq!(ulids[0].len(), 26);
+        assert!(first.timestamp_ms() <= now_ms());
+
+        assert_eq!(Format::Hex.entropy_bits(32), 128.0);
+        assert_eq!(Format::Nanoid.entropy_bits(NANOID_LEN), 126.0);
+        assert_eq!(Format::Uuid4.entropy_bits(99), 122.0);
+    }
+}
@@ -1,7 +1,20 @@
+mod config;
+mod docs;
+mod ids;
+mod output;
+mod password;
+mod reports;
+mod stats;
+
 use anyhow::{Context, Result};
-use clap::{ArgAction, Parser, Subcommand};
-use rand::{Rng, distr::Alphanumeric};
-use std::{fs, path::PathBuf};
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use config::{Config, Layer};
+use ids::{Format, Source};
+use output::{Code, Output, WithCode};
+use std::io::IsTerminal;
+use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(name = "mycli", version, about = "Example CLI Tool")]
@@ -14,7 +27,11 @@ struct Cli {
     #[arg(short = 'n', long, default_value_t = 16)]
     len: usize,
 
-    // If provided, ONLY read this file and print stats (contents + sizes)
+    // How results (and errors, on stderr) are written
+    #[arg(long, value_enum, global = true, default_value_t)]
+    output: Output,
+
+    // Shorthand for `stats PATH`
     #[arg(short = 'f', long, value_name = "PATH")]
     file: Option<PathBuf>,
 
@@ -36,65 +53,374 @@ enum Commands {
         b: i32,
     },
 
-    // Generate a random base62 ID
+    // Generate random IDs (base62 by default)
     Gen {
-        // Length of the ID (overrides top-level --len)
+        // Length of the ID (overrides top-level --len; nanoid defaults to
+        // 21, UUIDs and ULIDs have a fixed length)
         #[arg(short = 'n', long)]
         len: Option<usize>,
+
+        #[arg(short, long, value_enum, default_value_t)]
+        format: Format,
+
+        // How many IDs to print, one per line
+        #[arg(short, long, default_value_t = 1)]
+        count: usize,
+
+        // Put this in front of every ID
+        #[arg(long, default_value = "")]
+        prefix: String,
+
+        // Seed a deterministic RNG so the output repeats (not for secrets)
+        #[arg(long, conflicts_with = "os_rng")]
+        seed: Option<u64>,
+
+        // Draw every byte from the OS CSPRNG instead of the thread RNG
+        #[arg(long)]
+        os_rng: bool,
+
+        // Print the bits of entropy per ID to stderr
+        #[arg(long)]
+        entropy: bool,
+    },
+
+    // Generate passwords (or passphrases) meeting a policy, or check one
+    // read from stdin against it
+    Password {
+        #[arg(short = 'n', long, default_value_t = 20)]
+        length: usize,
+
+        #[arg(long, value_name = "N", default_value_t = 1)]
+        min_upper: usize,
+
+        #[arg(long, value_name = "N", default_value_t = 1)]
+        min_lower: usize,
+
+        #[arg(long, value_name = "N", default_value_t = 1)]
+        min_digit: usize,
+
+        #[arg(long, value_name = "N", default_value_t = 1)]
+        min_symbol: usize,
+
+        // Letters and digits only
+        #[arg(long, conflicts_with = "min_symbol")]
+        no_symbols: bool,
+
+        // Leave out characters that are easy to misread (0 O o 1 l I |)
+        #[arg(long)]
+        no_ambiguous: bool,
+
+        // Generate a passphrase of this many words instead
+        #[arg(short, long, value_name = "N", conflicts_with = "check")]
+        words: Option<usize>,
+
+        // Between passphrase words
+        #[arg(long, default_value = "-", requires = "words")]
+        separator: String,
+
+        #[arg(short, long, default_value_t = 1, conflicts_with = "check")]
+        count: usize,
+
+        // Read a password from stdin and check it against the policy
+        // instead (exits non-zero when it fails)
+        #[arg(long)]
+        check: bool,
+    },
+
+    // Count lines, words, chars, bytes and the longest line of each input
+    // (files, globs, or `-`/nothing for stdin), like wc
+    Stats {
+        inputs: Vec<String>,
+
+        #[arg(short, long, value_enum, default_value_t)]
+        format: stats::Format,
+
+        // Leave out the total row (it's only shown for several inputs)
+        #[arg(long)]
+        no_total: bool,
+    },
+
+    // Print a completion script for SHELL to stdout
+    Completions {
+        shell: Shell,
+    },
+
+    // Write roff man pages for mycli and each subcommand
+    Man {
+        // Directory to write the pages into
+        #[arg(short, long, value_name = "DIR", default_value = "man")]
+        out_dir: PathBuf,
+    },
+
+    // Inspect the merged configuration (/etc/mycli/config.toml,
+    // $XDG_CONFIG_HOME/mycli/config.toml, ./mycli.toml, MYCLI_* env, flags)
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
     },
 }
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
-    log(1, cli.verbose, "start");
+#[derive(Subcommand)]
+enum ConfigAction {
+    // Print the effective settings
+    Show {
+        // Also print where each value came from
+        #[arg(long)]
+        origin: bool,
+    },
+}
+
+// The flags that were actually typed, as the top configuration layer
+fn flag_layer(cli: &Cli, matches: &ArgMatches) -> Layer {
+    let given = |id| matches.value_source(id) == Some(ValueSource::CommandLine);
+    Layer {
+        len: given("len").then_some(cli.len),
+        verbose: given("verbose").then_some(cli.verbose),
+    }
+}
+
+fn main() {
+    let matches = match Cli::command().try_get_matches() {
+        Ok(matches) => matches,
+        Err(e) => usage_error(e),
+    };
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| usage_error(e));
+    if let Err(e) = run(&cli, &matches) {
+        std::process::exit(output::fail(&e, cli.output));
+    }
+}
 
-    // If --file provided → ONLY do file stats and exit.
+// Clap's own message and exit code in text mode; otherwise the structured
+// error, as long as --output itself could be read
+fn usage_error(e: clap::Error) -> ! {
+    let output = Cli::command()
+        .ignore_errors(true)
+        .try_get_matches()
+        .ok()
+        .and_then(|m| m.get_one::<Output>("output").copied())
+        .unwrap_or_default();
+    if !e.use_stderr() || output == Output::Text {
+        e.exit();
+    }
+    let message = e.kind().as_str().unwrap_or("invalid arguments").to_string();
+    let detail = e.render().to_string();
+    let report = output::ErrorReport::new(
+        Code::Usage,
+        detail
+            .lines()
+            .next()
+            .and_then(|l| l.strip_prefix("error: "))
+            .map_or(message, str::to_string),
+    );
+    eprint!("{}", output::render(&report, output).unwrap_or(detail));
+    std::process::exit(Code::Usage.exit_code());
+}
+
+fn run(cli: &Cli, matches: &ArgMatches) -> Result<()> {
+    let config = Config::load(flag_layer(cli, matches)).code(Code::Config)?;
+    let (verbose, default_len) = (config.verbose.value, config.len.value);
+    let out = cli.output;
+    log(1, verbose, "start");
+
+    // --file is the old spelling of `stats PATH`
     if let Some(path) = &cli.file {
-        log(2, cli.verbose, "file stats mode");
-        let content = fs::read_to_string(path)
-            .with_context(|| format!("failed to read {}", path.display()))?;
-        println!("--- {} ---", path.display());
-        println!("{content}");
-        let bytes = content.len();
-        let chars = content.chars().count();
-        println!("bytes: {bytes}");
-        println!("chars: {chars}");
-        log(1, cli.verbose, "done");
+        log(2, verbose, "file stats mode");
+        let input = vec![path.display().to_string()];
+        output::emit(&stats_report(&input, stats::Format::Table, false)?, out)?;
+        log(1, verbose, "done");
         return Ok(());
     }
 
     // Otherwise: run subcommand (or default = gen)
     match &cli.command {
         Some(Commands::Hello { name }) => {
-            log(2, cli.verbose, "hello");
-            println!("Hi, {name}!");
+            log(2, verbose, "hello");
+            output::emit(&reports::Greeting::new(name), out)?;
         }
         Some(Commands::Add { a, b }) => {
-            log(2, cli.verbose, "add");
-            println!("{a} + {b} = {}", a + b);
+            log(2, verbose, "add");
+            output::emit(&reports::Sum::new(*a, *b), out)?;
+        }
+        Some(Commands::Gen {
+            len,
+            format,
+            count,
+            prefix,
+            seed,
+            os_rng,
+            entropy,
+        }) => {
+            log(2, verbose, "gen");
+            let fallback = match format {
+                Format::Nanoid => ids::NANOID_LEN,
+                _ => default_len,
+            };
+            let use_len = len.unwrap_or(fallback);
+            let name = format
+                .to_possible_value()
+                .map(|v| v.get_name().to_string())
+                .unwrap_or_default();
+            if len.is_some() && !format.has_length() {
+                return Err(anyhow::anyhow!(
+                    "--len doesn't apply to {name}, its length is fixed"
+                ))
+                .code(Code::Usage);
+            }
+            let source = match (seed, os_rng) {
+                (Some(seed), _) => Source::Seeded(*seed),
+                (None, true) => Source::Os,
+                (None, false) => Source::Thread,
+            };
+            let mut rng = source.rng();
+            let ids = (0..*count)
+                .map(|_| format!("{prefix}{}", ids::generate(*format, use_len, &mut rng)))
+                .collect();
+            let report = reports::Ids {
+                format: name,
+                ids,
+                entropy_bits: entropy.then(|| format.entropy_bits(use_len)),
+            };
+            output::emit(&report, out)?;
+        }
+        Some(Commands::Password {
+            length,
+            min_upper,
+            min_lower,
+            min_digit,
+            min_symbol,
+            no_symbols,
+            no_ambiguous,
+            words,
+            separator,
+            count,
+            check,
+        }) => {
+            log(2, verbose, "password");
+            let policy = password::Policy {
+                length: *length,
+                min_upper: *min_upper,
+                min_lower: *min_lower,
+                min_digit: *min_digit,
+                min_symbol: *min_symbol,
+                symbols: !no_symbols,
+                no_ambiguous: *no_ambiguous,
+            };
+            let mut rng = rand::rng();
+            if *check {
+                let secret = read_secret()?;
+                let problems = policy.check(&secret);
+                let report = reports::PolicyCheck {
+                    ok: problems.is_empty(),
+                    problems,
+                    estimated_bits: password::estimate_bits(&secret),
+                };
+                output::emit(&report, out)?;
+                if !report.ok {
+                    return Err(anyhow::anyhow!("password does not meet the policy"))
+                        .code(Code::Policy);
+                }
+            } else if let Some(words) = words {
+                let report = reports::Secrets {
+                    kind: "passphrase",
+                    secrets: (0..*count)
+                        .map(|_| password::passphrase(*words, separator, &mut rng))
+                        .collect(),
+                    entropy_bits: password::passphrase_bits(*words),
+                };
+                output::emit(&report, out)?;
+            } else {
+                let secrets = (0..*count)
+                    .map(|_| policy.generate(&mut rng))
+                    .collect::<Result<_>>()
+                    .code(Code::Usage)?;
+                let report = reports::Secrets {
+                    kind: "password",
+                    secrets,
+                    entropy_bits: policy.entropy_bits(),
+                };
+                output::emit(&report, out)?;
+            }
         }
-        Some(Commands::Gen { len }) => {
-            log(2, cli.verbose, "gen");
-            let use_len = len.unwrap_or(cli.len);
-            println!("{}", gen_id(use_len));
+        Some(Commands::Stats {
+            inputs,
+            format,
+            no_total,
+        }) => {
+            log(2, verbose, "stats");
+            output::emit(&stats_report(inputs, *format, *no_total)?, out)?;
+        }
+        Some(Commands::Completions { shell }) => {
+            log(2, verbose, "completions");
+            let report = reports::Completions {
+                shell: shell.to_string(),
+                script: docs::completions(*shell, Cli::command()),
+            };
+            output::emit(&report, out)?;
+        }
+        Some(Commands::Man { out_dir }) => {
+            log(2, verbose, "man");
+            let pages = docs::man_pages(Cli::command(), out_dir)?
+                .iter()
+                .map(|page| out_dir.join(page).display().to_string())
+                .collect();
+            output::emit(&reports::ManPages { pages }, out)?;
+        }
+        Some(Commands::Config {
+            action: ConfigAction::Show { origin },
+        }) => {
+            log(2, verbose, "config show");
+            let report = reports::ConfigShow {
+                config: &config,
+                origin: *origin,
+            };
+            output::emit(&report, out)?;
         }
         None => {
             // default = gen
-            log(2, cli.verbose, "gen (default)");
-            println!("{}", gen_id(cli.len));
+            log(2, verbose, "gen (default)");
+            let report = reports::Ids {
+                format: "base62".to_string(),
+                ids: vec![ids::generate(Format::Base62, default_len, &mut rand::rng())],
+                entropy_bits: None,
+            };
+            output::emit(&report, out)?;
         }
     }
 
-    log(1, cli.verbose, "done");
+    log(1, verbose, "done");
     Ok(())
 }
 
-fn gen_id(len: usize) -> String {
-    rand::rng()
-        .sample_iter(&Alphanumeric)
-        .take(len)
-        .map(char::from)
-        .collect()
+fn stats_report(
+    inputs: &[String],
+    format: stats::Format,
+    no_total: bool,
+) -> Result<reports::Stats> {
+    let files = stats::expand(inputs)
+        .code(Code::Usage)?
+        .iter()
+        .map(stats::count_path)
+        .collect::<Result<Vec<_>>>()?;
+    let total = (files.len() > 1 && !no_total).then(|| stats::total(&files));
+    Ok(reports::Stats {
+        files,
+        total,
+        format,
+    })
+}
+
+// One line from stdin without its line ending; prompts when it's a
+// terminal so the password stays out of argv and shell history
+fn read_secret() -> Result<String> {
+    let stdin = std::io::stdin();
+    if stdin.is_terminal() {
+        eprint!("Password: ");
+    }
+    let mut line = String::new();
+    stdin
+        .read_line(&mut line)
+        .context("failed to read password from stdin")?;
+    Ok(line.trim_end_matches(['\n', '\r']).to_string())
 }
 
 // Log to stderr if `verbose` level is high enough
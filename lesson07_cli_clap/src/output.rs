@@ -0,0 +1,315 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Output {
+    #[default]
+    Text,
+    Json,
+    Yaml,
+    // Header row, then one row per record
+    Tsv,
+}
+
+// What every command hands back. The machine formats come from `Serialize`;
+// text is the human output and `rows` the tabular view for TSV.
+pub trait Report: Serialize {
+    fn text(&self) -> String;
+
+    // Header first
+    fn rows(&self) -> Vec<Vec<String>>;
+
+    // Extra human-only line for stderr in text mode (entropy estimates etc.)
+    fn note(&self) -> Option<String> {
+        None
+    }
+}
+
+pub fn render(report: &impl Report, output: Output) -> Result<String> {
+    Ok(match output {
+        Output::Text => report.text(),
+        Output::Json => serde_json::to_string_pretty(report)? + "\n",
+        Output::Yaml => serde_norway::to_string(report)?,
+        Output::Tsv => report
+            .rows()
+            .iter()
+            .map(|row| {
+                let fields: Vec<String> = row.iter().map(|f| tsv_field(f)).collect();
+                fields.join("\t") + "\n"
+            })
+            .collect(),
+    })
+}
+
+// Print `report` to stdout and, in text mode, its note to stderr
+pub fn emit(report: &impl Report, output: Output) -> Result<()> {
+    print!("{}", render(report, output)?);
+    if output == Output::Text
+        && let Some(note) = report.note()
+    {
+        eprintln!("{note}");
+    }
+    Ok(())
+}
+
+fn tsv_field(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+// Why a run failed. The names and exit codes are stable: scripts match on
+// them, so only ever add new ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Code {
+    // Anything not classified below
+    Failed,
+    // Bad arguments or flag combinations
+    Usage,
+    // A config file or MYCLI_* variable couldn't be used
+    Config,
+    // Reading or writing a file or stdin failed
+    Io,
+    // A password checked with `password --check` doesn't meet the policy
+    Policy,
+}
+
+impl Code {
+    pub fn name(self) -> &'static str {
+        match self {
+            Code::Failed => "failed",
+            Code::Usage => "usage",
+            Code::Config => "config",
+            Code::Io => "io",
+            Code::Policy => "policy",
+        }
+    }
+
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Code::Failed => 1,
+            Code::Usage => 2,
+            Code::Config => 3,
+            Code::Io => 4,
+            Code::Policy => 5,
+        }
+    }
+
+    // The code tagged onto `err` with `.code()`, else `Io` if an I/O error
+    // caused it
+    pub fn of(err: &anyhow::Error) -> Code {
+        if let Some(coded) = err.chain().find_map(|e| e.downcast_ref::<Coded>()) {
+            return coded.code;
+        }
+        if err.chain().any(|e| e.is::<std::io::Error>()) {
+            return Code::Io;
+        }
+        Code::Failed
+    }
+}
+
+// An error tagged with its Code. Displays as the error it wraps and hands
+// on that error's causes, so the message people see doesn't change.
+#[derive(Debug)]
+pub struct Coded {
+    code: Code,
+    inner: anyhow::Error,
+}
+
+impl fmt::Display for Coded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.inner, f)
+    }
+}
+
+impl std::error::Error for Coded {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.inner.source()
+    }
+}
+
+pub trait WithCode<T> {
+    fn code(self, code: Code) -> Result<T>;
+}
+
+impl<T, E: Into<anyhow::Error>> WithCode<T> for std::result::Result<T, E> {
+    fn code(self, code: Code) -> Result<T> {
+        self.map_err(|e| {
+            anyhow::Error::new(Coded {
+                code,
+                inner: e.into(),
+            })
+        })
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct ErrorReport {
+    pub error: ErrorBody,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ErrorBody {
+    pub code: &'static str,
+    pub exit_code: i32,
+    pub message: String,
+}
+
+impl ErrorReport {
+    pub fn new(code: Code, message: String) -> Self {
+        Self {
+            error: ErrorBody {
+                code: code.name(),
+                exit_code: code.exit_code(),
+                message,
+            },
+        }
+    }
+
+    pub fn from_error(err: &anyhow::Error) -> Self {
+        Self::new(Code::of(err), format!("{err:#}"))
+    }
+}
+
+impl Report for ErrorReport {
+    // Same shape as anyhow's own `Error: ...` from `main`
+    fn text(&self) -> String {
+        format!("Error: {}\n", self.error.message)
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        vec![
+            vec!["code".into(), "exit_code".into(), "message".into()],
+            vec![
+                self.error.code.into(),
+                self.error.exit_code.to_string(),
+                self.error.message.clone(),
+            ],
+        ]
+    }
+}
+
+// Report `err` on stderr in the chosen format and return the exit code
+pub fn fail(err: &anyhow::Error, output: Output) -> i32 {
+    let report = ErrorReport::from_error(err);
+    match output {
+        // Keep the cause chain and backtrace people get today
+        Output::Text => eprintln!("Error: {err:?}"),
+        _ => eprint!(
+            "{}",
+            render(&report, output).unwrap_or_else(|_| report.text())
+        ),
+    }
+    report.error.exit_code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[derive(Serialize)]
+    struct Pair {
+        key: String,
+        value: u32,
+    }
+
+    impl Report for Pair {
+        fn text(&self) -> String {
+            format!("{} = {}\n", self.key, self.value)
+        }
+
+        fn rows(&self) -> Vec<Vec<String>> {
+            vec![
+                vec!["key".into(), "value".into()],
+                vec![self.key.clone(), self.value.to_string()],
+            ]
+        }
+    }
+
+    #[test]
+    fn renders_every_format() {
+        let pair = Pair {
+            key: "a\tb".into(),
+            value: 7,
+        };
+        assert_eq!(render(&pair, Output::Text).unwrap(), "a\tb = 7\n");
+        let json: serde_json::Value =
+            serde_json::from_str(&render(&pair, Output::Json).unwrap()).unwrap();
+        assert_eq!(json["value"], 7);
+        assert_eq!(
+            render(&pair, Output::Yaml).unwrap(),
+            "key: \"a\\tb\"\nvalue: 7\n"
+        );
+        assert_eq!(
+            render(&pair, Output::Tsv).unwrap(),
+            "key\tvalue\na\\tb\t7\n"
+        );
+    }
+
+    #[test]
+    fn errors_carry_stable_codes() {
+        let tagged = Err::<(), _>(anyhow::anyhow!("invalid digit found in string"))
+            .context("bad value in MYCLI_LEN: `x`")
+            .code(Code::Config)
+            .context("while starting")
+            .unwrap_err();
+        assert_eq!(Code::of(&tagged), Code::Config);
+
+        let io = std::fs::read("/nonexistent/mycli")
+            .context("failed to read /nonexistent/mycli")
+            .unwrap_err();
+        assert_eq!(Code::of(&io), Code::Io);
+        assert_eq!(Code::of(&anyhow::anyhow!("boom")), Code::Failed);
+
+        let report = ErrorReport::from_error(&tagged);
+        let json: serde_json::Value =
+            serde_json::from_str(&render(&report, Output::Json).unwrap()).unwrap();
+        assert_eq!(json["error"]["code"], "config");
+        assert_eq!(json["error"]["exit_code"], 3);
+        assert_eq!(
+            json["error"]["message"],
+            "while starting: bad value in MYCLI_LEN: `x`: invalid digit found in string"
+        );
+    }
+
+    #[test]
+    fn codes_leave_the_message_alone() {
+        let err = Err::<(), _>(anyhow::anyhow!(
+            "--len doesn't apply to uuid4, its length is fixed"
+        ))
+        .code(Code::Usage)
+        .unwrap_err();
+        assert_eq!(Code::of(&err), Code::Usage);
+        // What `fail` prints in text mode, minus any backtrace
+        assert!(
+            format!("Error: {err:?}")
+                .starts_with("Error: --len doesn't apply to uuid4, its length is fixed")
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&render(&ErrorReport::from_error(&err), Output::Json).unwrap())
+                .unwrap();
+        assert_eq!(json["error"]["code"], "usage");
+        assert_eq!(
+            json["error"]["message"],
+            "--len doesn't apply to uuid4, its length is fixed"
+        );
+
+        // Causes of the tagged error still show up after it
+        let err = Err::<(), _>(anyhow::anyhow!("invalid digit found in string"))
+            .context("bad value in MYCLI_LEN: `x`")
+            .code(Code::Config)
+            .unwrap_err();
+        let text = format!("{err:?}");
+        let mut lines = text.lines();
+        assert_eq!(lines.next(), Some("bad value in MYCLI_LEN: `x`"));
+        assert!(text.contains("Caused by:\n    invalid digit found in string"));
+        assert_eq!(
+            ErrorReport::from_error(&err).error.message,
+            "bad value in MYCLI_LEN: `x`: invalid digit found in string"
+        );
+    }
+}
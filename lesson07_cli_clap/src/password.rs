@@ -0,0 +1,241 @@
+use anyhow::Result;
+use rand::seq::SliceRandom;
+use rand::{Rng, RngCore};
+
+const UPPER: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const LOWER: &str = "abcdefghijklmnopqrstuvwxyz";
+const DIGITS: &str = "0123456789";
+// Printable ASCII punctuation minus quotes, backslash and backtick, which
+// tend to break when pasted into shells and config files
+const SYMBOLS: &str = "!#$%&()*+,-./:;<=>?@[]^_{|}~";
+// Characters easily misread for one another
+const AMBIGUOUS: &str = "0Oo1lI|";
+// The BIP-39 English list: 2048 short, distinct words, 11 bits each
+const WORDS: &str = include_str!("wordlist.txt");
+
+#[derive(Clone, Debug)]
+pub struct Policy {
+    pub length: usize,
+    pub min_upper: usize,
+    pub min_lower: usize,
+    pub min_digit: usize,
+    pub min_symbol: usize,
+    // Leave symbols out entirely (`min_symbol` is then ignored)
+    pub symbols: bool,
+    // Never generate anything from `AMBIGUOUS`, and reject it when checking
+    pub no_ambiguous: bool,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            length: 20,
+            min_upper: 1,
+            min_lower: 1,
+            min_digit: 1,
+            min_symbol: 1,
+            symbols: true,
+            no_ambiguous: false,
+        }
+    }
+}
+
+struct Class {
+    name: &'static str,
+    // The whole class, and the part of it we generate from
+    set: &'static str,
+    chars: Vec<char>,
+    min: usize,
+}
+
+impl Policy {
+    fn classes(&self) -> Vec<Class> {
+        let mut classes = vec![
+            ("uppercase letter", UPPER, self.min_upper),
+            ("lowercase letter", LOWER, self.min_lower),
+            ("digit", DIGITS, self.min_digit),
+        ];
+        if self.symbols {
+            classes.push(("symbol", SYMBOLS, self.min_symbol));
+        }
+        classes
+            .into_iter()
+            .map(|(name, set, min)| Class {
+                name,
+                set,
+                chars: set
+                    .chars()
+                    .filter(|c| !(self.no_ambiguous && AMBIGUOUS.contains(*c)))
+                    .collect(),
+                min,
+            })
+            .collect()
+    }
+
+    // Every class minimum first, the rest from all allowed characters, then
+    // shuffled so the required ones don't sit at the front
+    pub fn generate(&self, rng: &mut dyn RngCore) -> Result<String> {
+        let classes = self.classes();
+        let required: usize = classes.iter().map(|c| c.min).sum();
+        anyhow::ensure!(
+            required <= self.length,
+            "the minimums add up to {required} characters, more than --length {}",
+            self.length
+        );
+        let pool: Vec<char> = classes.iter().flat_map(|c| c.chars.clone()).collect();
+        let mut out = Vec::with_capacity(self.length);
+        for class in &classes {
+            for _ in 0..class.min {
+                out.push(class.chars[rng.random_range(0..class.chars.len())]);
+            }
+        }
+        while out.len() < self.length {
+            out.push(pool[rng.random_range(0..pool.len())]);
+        }
+        out.shuffle(rng);
+        Ok(out.into_iter().collect())
+    }
+
+    // Bits for a generated password: a slight overestimate, since the
+    // minimums make some strings impossible
+    pub fn entropy_bits(&self) -> f64 {
+        let pool: usize = self.classes().iter().map(|c| c.chars.len()).sum();
+        self.length as f64 * (pool as f64).log2()
+    }
+
+    // What `password` gets wrong under this policy; empty when it passes
+    pub fn check(&self, password: &str) -> Vec<String> {
+        let mut problems = Vec::new();
+        let len = password.chars().count();
+        if len < self.length {
+            problems.push(format!(
+                "{len} characters, at least {} required",
+                self.length
+            ));
+        }
+        for class in self.classes() {
+            let have = password.chars().filter(|c| class.set.contains(*c)).count();
+            if have < class.min {
+                problems.push(format!(
+                    "{have} {}(s), at least {} required",
+                    class.name, class.min
+                ));
+            }
+        }
+        if self.no_ambiguous && password.contains(|c| AMBIGUOUS.contains(c)) {
+            problems.push(format!("contains ambiguous characters ({AMBIGUOUS})"));
+        }
+        problems
+    }
+}
+
+// Rough strength of an arbitrary password: its length times the bits of
+// the smallest character set that covers it (brute-force cost, ignoring
+// dictionary words and patterns)
+pub fn estimate_bits(password: &str) -> f64 {
+    let mut charset = 0;
+    for set in [UPPER, LOWER, DIGITS] {
+        if password.contains(|c| set.contains(c)) {
+            charset += set.len();
+        }
+    }
+    if password
+        .chars()
+        .any(|c| c.is_ascii_punctuation() || c == ' ')
+    {
+        // All of printable ASCII punctuation, not just what we generate
+        charset += 33;
+    }
+    if !password.is_ascii() {
+        charset += 100;
+    }
+    password.chars().count() as f64 * (charset.max(1) as f64).log2()
+}
+
+// `words` random words from the embedded list joined by `separator`
+pub fn passphrase(words: usize, separator: &str, rng: &mut dyn RngCore) -> String {
+    let list: Vec<&str> = WORDS.lines().collect();
+    (0..words)
+        .map(|_| list[rng.random_range(0..list.len())])
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+pub fn passphrase_bits(words: usize) -> f64 {
+    words as f64 * (WORDS.lines().count() as f64).log2()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ids::Source;
+
+    #[test]
+    fn generated_passwords_pass_their_own_policy() {
+        let mut rng = Source::Seeded(3).rng();
+        let policy = Policy {
+            length: 12,
+            min_digit: 4,
+            min_symbol: 3,
+            no_ambiguous: true,
+            ..Policy::default()
+        };
+        for _ in 0..200 {
+            let pw = policy.generate(&mut rng).unwrap();
+            assert_eq!(pw.chars().count(), 12);
+            assert!(
+                policy.check(&pw).is_empty(),
+                "{pw}: {:?}",
+                policy.check(&pw)
+            );
+        }
+
+        let no_symbols = Policy {
+            symbols: false,
+            ..Policy::default()
+        };
+        let pw = no_symbols.generate(&mut rng).unwrap();
+        assert!(pw.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_eq!(
+            no_symbols.entropy_bits(),
+            20.0 * 62f64.log2(),
+            "62 alphanumerics"
+        );
+
+        let too_many = Policy {
+            length: 3,
+            ..Policy::default()
+        };
+        assert!(too_many.generate(&mut rng).is_err());
+    }
+
+    #[test]
+    fn check_names_every_problem() {
+        let policy = Policy {
+            length: 10,
+            no_ambiguous: true,
+            ..Policy::default()
+        };
+        assert_eq!(
+            policy.check("password1"),
+            [
+                "9 characters, at least 10 required",
+                "0 uppercase letter(s), at least 1 required",
+                "0 symbol(s), at least 1 required",
+                "contains ambiguous characters (0Oo1lI|)",
+            ]
+        );
+        assert!(policy.check("Brave-Chest-7-Battery").is_empty());
+        assert_eq!(estimate_bits("abcd"), 4.0 * 26f64.log2());
+    }
+
+    #[test]
+    fn passphrases_come_from_the_wordlist() {
+        let mut rng = Source::Seeded(5).rng();
+        let phrase = passphrase(6, ".", &mut rng);
+        let words: Vec<&str> = phrase.split('.').collect();
+        assert_eq!(words.len(), 6);
+        assert!(words.iter().all(|w| WORDS.lines().any(|l| l == *w)));
+        assert_eq!(passphrase_bits(6), 66.0);
+    }
+}
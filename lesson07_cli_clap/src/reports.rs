@@ -0,0 +1,299 @@
+use crate::config::Config;
+use crate::output::Report;
+use crate::stats::{self, FileStats};
+use serde::Serialize;
+
+fn header(names: &[&str]) -> Vec<String> {
+    names.iter().map(|n| n.to_string()).collect()
+}
+
+fn opt(v: Option<u64>) -> String {
+    v.map_or(String::new(), |v| v.to_string())
+}
+
+#[derive(Serialize, Debug)]
+pub struct Greeting {
+    pub name: String,
+    pub greeting: String,
+}
+
+impl Greeting {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            greeting: format!("Hi, {name}!"),
+        }
+    }
+}
+
+impl Report for Greeting {
+    fn text(&self) -> String {
+        format!("{}\n", self.greeting)
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        vec![
+            header(&["name", "greeting"]),
+            vec![self.name.clone(), self.greeting.clone()],
+        ]
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Sum {
+    pub a: i32,
+    pub b: i32,
+    // Wide enough that two i32s never overflow
+    pub sum: i64,
+}
+
+impl Sum {
+    pub fn new(a: i32, b: i32) -> Self {
+        Self {
+            a,
+            b,
+            sum: a as i64 + b as i64,
+        }
+    }
+}
+
+impl Report for Sum {
+    fn text(&self) -> String {
+        format!("{} + {} = {}\n", self.a, self.b, self.sum)
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        vec![
+            header(&["a", "b", "sum"]),
+            vec![self.a.to_string(), self.b.to_string(), self.sum.to_string()],
+        ]
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Ids {
+    pub format: String,
+    pub ids: Vec<String>,
+    // Only with `gen --entropy`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entropy_bits: Option<f64>,
+}
+
+impl Report for Ids {
+    fn text(&self) -> String {
+        self.ids.iter().map(|id| format!("{id}\n")).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        let mut rows = vec![header(&["id"])];
+        rows.extend(self.ids.iter().map(|id| vec![id.clone()]));
+        rows
+    }
+
+    fn note(&self) -> Option<String> {
+        self.entropy_bits
+            .map(|bits| format!("{bits:.1} bits of entropy per ID"))
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Secrets {
+    // `password` or `passphrase`
+    pub kind: &'static str,
+    pub secrets: Vec<String>,
+    pub entropy_bits: f64,
+}
+
+impl Report for Secrets {
+    fn text(&self) -> String {
+        self.secrets.iter().map(|s| format!("{s}\n")).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        let mut rows = vec![header(&[self.kind])];
+        rows.extend(self.secrets.iter().map(|s| vec![s.clone()]));
+        rows
+    }
+
+    // Passwords are only estimated: class minimums skew the distribution
+    fn note(&self) -> Option<String> {
+        let approx = if self.kind == "password" { "~" } else { "" };
+        Some(format!("{approx}{:.0} bits of entropy", self.entropy_bits))
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct PolicyCheck {
+    pub ok: bool,
+    pub problems: Vec<String>,
+    pub estimated_bits: f64,
+}
+
+impl Report for PolicyCheck {
+    fn text(&self) -> String {
+        let mut out: String = self.problems.iter().map(|p| format!("- {p}\n")).collect();
+        if self.ok {
+            out.push_str("ok\n");
+        }
+        out
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        let mut rows = vec![header(&["problem"])];
+        rows.extend(self.problems.iter().map(|p| vec![p.clone()]));
+        rows
+    }
+
+    fn note(&self) -> Option<String> {
+        Some(format!(
+            "~{:.0} bits of entropy (estimated)",
+            self.estimated_bits
+        ))
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Completions {
+    pub shell: String,
+    pub script: String,
+}
+
+impl Report for Completions {
+    fn text(&self) -> String {
+        self.script.clone()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        vec![
+            header(&["shell", "script"]),
+            vec![self.shell.clone(), self.script.clone()],
+        ]
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct ManPages {
+    // Paths of the pages written
+    pub pages: Vec<String>,
+}
+
+impl Report for ManPages {
+    fn text(&self) -> String {
+        self.pages.iter().map(|p| format!("{p}\n")).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        let mut rows = vec![header(&["page"])];
+        rows.extend(self.pages.iter().map(|p| vec![p.clone()]));
+        rows
+    }
+}
+
+// `config show`: every setting with its value and origin
+#[derive(Serialize, Debug)]
+pub struct ConfigShow<'a> {
+    #[serde(flatten)]
+    pub config: &'a Config,
+    #[serde(skip)]
+    pub origin: bool,
+}
+
+impl Report for ConfigShow<'_> {
+    fn text(&self) -> String {
+        self.config.show(self.origin)
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        let c = self.config;
+        vec![
+            header(&["key", "value", "origin"]),
+            vec![
+                "len".into(),
+                c.len.value.to_string(),
+                c.len.origin.to_string(),
+            ],
+            vec![
+                "verbose".into(),
+                c.verbose.value.to_string(),
+                c.verbose.origin.to_string(),
+            ],
+        ]
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Stats {
+    pub files: Vec<FileStats>,
+    pub total: Option<FileStats>,
+    // How text mode lays it out (`stats --format`)
+    #[serde(skip)]
+    pub format: stats::Format,
+}
+
+impl Report for Stats {
+    fn text(&self) -> String {
+        stats::render(&self.files, self.total.as_ref(), self.format)
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        let mut rows = vec![header(&[
+            "name",
+            "lines",
+            "words",
+            "chars",
+            "bytes",
+            "max_line_length",
+            "encoding",
+        ])];
+        rows.extend(self.files.iter().chain(&self.total).map(|r| {
+            vec![
+                r.name.clone(),
+                opt(r.lines),
+                opt(r.words),
+                opt(r.chars),
+                r.bytes.to_string(),
+                opt(r.max_line_length),
+                r.encoding.clone(),
+            ]
+        }));
+        rows
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::{Output, render};
+
+    #[test]
+    fn text_matches_the_plain_output() {
+        assert_eq!(Greeting::new("Ann").text(), "Hi, Ann!\n");
+        assert_eq!(
+            Sum::new(i32::MAX, 1).text(),
+            "2147483647 + 1 = 2147483648\n"
+        );
+        let check = PolicyCheck {
+            ok: false,
+            problems: vec!["too short".into()],
+            estimated_bits: 12.0,
+        };
+        assert_eq!(check.text(), "- too short\n");
+    }
+
+    #[test]
+    fn config_serializes_values_with_origins() {
+        let config = Config::default();
+        let show = ConfigShow {
+            config: &config,
+            origin: true,
+        };
+        let json: serde_json::Value =
+            serde_json::from_str(&render(&show, Output::Json).unwrap()).unwrap();
+        assert_eq!(json["len"]["value"], 16);
+        assert_eq!(json["verbose"]["origin"], "default");
+        assert_eq!(
+            render(&show, Output::Tsv).unwrap().lines().nth(1),
+            Some("len\t16\tdefault")
+        );
+    }
+}
@@ -0,0 +1,406 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+// Bytes looked at for a NUL before calling a file binary (as git and grep do)
+const SNIFF_LEN: usize = 8 * 1024;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    #[default]
+    Table,
+    Json,
+    Csv,
+}
+
+// Counts for one input. Text counts are `None` for binary and UTF-16 input,
+// where only the byte count means anything.
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct FileStats {
+    pub name: String,
+    pub bytes: u64,
+    pub lines: Option<u64>,
+    pub words: Option<u64>,
+    pub chars: Option<u64>,
+    pub max_line_length: Option<u64>,
+    // ascii, utf-8, utf-8-bom, utf-16le, utf-16be, unknown or binary
+    pub encoding: String,
+}
+
+// Running counts, fed one buffer at a time
+#[derive(Default)]
+struct Counter {
+    bytes: u64,
+    lines: u64,
+    words: u64,
+    chars: u64,
+    line_chars: u64,
+    max_line: u64,
+    in_word: bool,
+    ascii: bool,
+    // A multi-byte sequence cut off at the end of the last buffer
+    carry: Vec<u8>,
+    valid_utf8: bool,
+}
+
+impl Counter {
+    fn new() -> Self {
+        Self {
+            ascii: true,
+            valid_utf8: true,
+            ..Self::default()
+        }
+    }
+
+    fn feed(&mut self, buf: &[u8]) {
+        self.bytes += buf.len() as u64;
+        for &b in buf {
+            let space = b.is_ascii_whitespace();
+            if !space && !self.in_word {
+                self.words += 1;
+            }
+            self.in_word = !space;
+            // Count UTF-8 scalar values by their first byte
+            let starts_char = b & 0xC0 != 0x80;
+            match b {
+                b'\n' => {
+                    self.lines += 1;
+                    self.max_line = self.max_line.max(self.line_chars);
+                    self.line_chars = 0;
+                }
+                b'\r' => {}
+                _ if starts_char => self.line_chars += 1,
+                _ => {}
+            }
+            if starts_char {
+                self.chars += 1;
+            }
+            self.ascii &= b.is_ascii();
+        }
+        if self.valid_utf8 {
+            self.check_utf8(buf);
+        }
+    }
+
+    // Only the (at most 3-byte) tail of a character split across buffers is
+    // ever copied; everything else is checked where it lies
+    fn check_utf8(&mut self, mut buf: &[u8]) {
+        while !self.carry.is_empty() {
+            let Some((&b, rest)) = buf.split_first() else {
+                return;
+            };
+            self.carry.push(b);
+            buf = rest;
+            match std::str::from_utf8(&self.carry) {
+                Ok(_) => self.carry.clear(),
+                Err(e) if e.error_len().is_some() => {
+                    self.valid_utf8 = false;
+                    return;
+                }
+                Err(_) => {}
+            }
+        }
+        if let Err(e) = std::str::from_utf8(buf) {
+            match e.error_len() {
+                // Incomplete at the end: wait for the next buffer
+                None => self.carry = buf[e.valid_up_to()..].to_vec(),
+                Some(_) => self.valid_utf8 = false,
+            }
+        }
+    }
+
+    fn finish(mut self, name: String, encoding: Option<&str>) -> FileStats {
+        self.max_line = self.max_line.max(self.line_chars);
+        let encoding = match encoding {
+            Some(e) => e.to_string(),
+            None if !self.carry.is_empty() || !self.valid_utf8 => "unknown".to_string(),
+            None if self.ascii => "ascii".to_string(),
+            None => "utf-8".to_string(),
+        };
+        let text = !matches!(encoding.as_str(), "binary" | "utf-16le" | "utf-16be");
+        FileStats {
+            name,
+            bytes: self.bytes,
+            lines: text.then_some(self.lines),
+            words: text.then_some(self.words),
+            chars: text.then_some(self.chars),
+            max_line_length: text.then_some(self.max_line),
+            encoding,
+        }
+    }
+}
+
+// Stream `input` through the counters without holding it in memory
+pub fn count(name: &str, input: impl Read) -> io::Result<FileStats> {
+    let mut reader = BufReader::with_capacity(64 * 1024, input);
+    let mut counter = Counter::new();
+
+    // Look at the start first: BOMs and NULs decide what the rest means
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    (&mut reader)
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut head)?;
+    let encoding = if head.starts_with(&[0xEF, 0xBB, 0xBF]) {
+        Some("utf-8-bom")
+    } else if head.starts_with(&[0xFF, 0xFE]) {
+        Some("utf-16le")
+    } else if head.starts_with(&[0xFE, 0xFF]) {
+        Some("utf-16be")
+    } else if head.contains(&0) {
+        Some("binary")
+    } else {
+        None
+    };
+    let skip_bom = if encoding == Some("utf-8-bom") { 3 } else { 0 };
+    counter.feed(&head[skip_bom..]);
+    counter.bytes += skip_bom as u64;
+
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            break;
+        }
+        let n = buf.len();
+        counter.feed(buf);
+        reader.consume(n);
+    }
+    Ok(counter.finish(name.to_string(), encoding))
+}
+
+// Turn the command-line inputs into paths: globs are expanded (and must
+// match something), `-` stays for stdin, no inputs at all means stdin. A
+// file that exists is taken as named, even with `[` or `?` in its name.
+pub fn expand(inputs: &[String]) -> Result<Vec<PathBuf>> {
+    if inputs.is_empty() {
+        return Ok(vec![PathBuf::from("-")]);
+    }
+    let mut paths = Vec::new();
+    for input in inputs {
+        if input == "-" || !input.contains(['*', '?', '[']) || Path::new(input).exists() {
+            paths.push(PathBuf::from(input));
+            continue;
+        }
+        let before = paths.len();
+        for entry in glob::glob(input).with_context(|| format!("bad glob pattern `{input}`"))? {
+            let path = entry?;
+            if path.is_file() {
+                paths.push(path);
+            }
+        }
+        anyhow::ensure!(paths.len() > before, "no files match `{input}`");
+    }
+    Ok(paths)
+}
+
+pub fn count_path(path: &PathBuf) -> Result<FileStats> {
+    if path.as_os_str() == "-" {
+        return count("-", io::stdin().lock()).context("failed to read stdin");
+    }
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    count(&path.display().to_string(), file)
+        .with_context(|| format!("failed to read {}", path.display()))
+}
+
+// Sums over all rows; the longest line is the longest anywhere
+pub fn total(rows: &[FileStats]) -> FileStats {
+    let sum = |f: fn(&FileStats) -> Option<u64>| rows.iter().filter_map(f).sum::<u64>();
+    let text = rows.iter().any(|r| r.lines.is_some());
+    FileStats {
+        name: "total".to_string(),
+        bytes: rows.iter().map(|r| r.bytes).sum(),
+        lines: text.then(|| sum(|r| r.lines)),
+        words: text.then(|| sum(|r| r.words)),
+        chars: text.then(|| sum(|r| r.chars)),
+        max_line_length: rows.iter().filter_map(|r| r.max_line_length).max(),
+        encoding: "-".to_string(),
+    }
+}
+
+fn cell(v: Option<u64>) -> String {
+    v.map_or_else(|| "-".to_string(), |v| v.to_string())
+}
+
+pub fn render(rows: &[FileStats], total: Option<&FileStats>, format: Format) -> String {
+    let all: Vec<&FileStats> = rows.iter().chain(total).collect();
+    match format {
+        Format::Json => {
+            let json = serde_json::json!({
+                "files": rows,
+                "total": total,
+            });
+            format!("{json:#}\n")
+        }
+        Format::Csv => {
+            let mut out = String::from("name,lines,words,chars,bytes,max_line_length,encoding\n");
+            for r in all {
+                out.push_str(&format!(
+                    "{},{},{},{},{},{},{}\n",
+                    csv_field(&r.name),
+                    r.lines.map_or(String::new(), |v| v.to_string()),
+                    r.words.map_or(String::new(), |v| v.to_string()),
+                    r.chars.map_or(String::new(), |v| v.to_string()),
+                    r.bytes,
+                    r.max_line_length.map_or(String::new(), |v| v.to_string()),
+                    r.encoding
+                ));
+            }
+            out
+        }
+        Format::Table => {
+            let header = ["lines", "words", "chars", "bytes", "max-line", "encoding"];
+            let cells: Vec<[String; 6]> = all
+                .iter()
+                .map(|r| {
+                    [
+                        cell(r.lines),
+                        cell(r.words),
+                        cell(r.chars),
+                        r.bytes.to_string(),
+                        cell(r.max_line_length),
+                        r.encoding.clone(),
+                    ]
+                })
+                .collect();
+            let mut widths = header.map(str::len);
+            for row in &cells {
+                for (w, c) in widths.iter_mut().zip(row) {
+                    *w = (*w).max(c.len());
+                }
+            }
+            let line = |row: &[String], name: &str| {
+                let numbers: Vec<String> = row[..5]
+                    .iter()
+                    .zip(widths)
+                    .map(|(c, w)| format!("{c:>w$}"))
+                    .collect();
+                format!(
+                    "{}  {:<w$}  {name}\n",
+                    numbers.join(" "),
+                    row[5],
+                    w = widths[5]
+                )
+            };
+            let mut out = line(&header.map(String::from), "name");
+            for (row, r) in cells.iter().zip(&all) {
+                out.push_str(&line(row, &r.name));
+            }
+            out
+        }
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // Hands out one byte per read, so every multi-byte character straddles
+    // a buffer boundary
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let Some((first, rest)) = self.0.split_first() else {
+                return Ok(0);
+            };
+            buf[0] = *first;
+            self.0 = rest;
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn counts_like_wc() {
+        let text = "héllo wörld\n  two  words\r\nlast line, no newline";
+        let s = count("t", Trickle(text.as_bytes())).unwrap();
+        assert_eq!(s.bytes, text.len() as u64);
+        assert_eq!(s.lines, Some(2));
+        assert_eq!(s.words, Some(8));
+        assert_eq!(s.chars, Some(text.chars().count() as u64));
+        assert_eq!(s.max_line_length, Some(21));
+        assert_eq!(s.encoding, "utf-8");
+
+        // Same counts when the data is bigger than the sniffed head
+        let big = "abc def\n".repeat(5_000);
+        let s = count("big", big.as_bytes()).unwrap();
+        assert_eq!(
+            (s.lines, s.words, s.bytes),
+            (Some(5_000), Some(10_000), 40_000)
+        );
+        assert_eq!(s.encoding, "ascii");
+    }
+
+    #[test]
+    fn detects_binary_and_encodings() {
+        let bin = count("b", &[0x7F, b'E', b'L', b'F', 0, 1, 2][..]).unwrap();
+        assert_eq!((bin.encoding.as_str(), bin.lines), ("binary", None));
+        let latin1 = count("l", &b"caf\xe9\n"[..]).unwrap();
+        assert_eq!(latin1.encoding, "unknown");
+        let bom = count("u", &b"\xEF\xBB\xBFhi\n"[..]).unwrap();
+        assert_eq!(
+            (bom.encoding.as_str(), bom.chars, bom.bytes),
+            ("utf-8-bom", Some(3), 6)
+        );
+        // A bad continuation byte right after a buffer boundary
+        let split = count("s", Trickle(&b"ok \xC3\xA9 \xE2\x82x\n"[..])).unwrap();
+        assert_eq!(split.encoding, "unknown");
+        let utf16 = count("w", &b"\xFF\xFEh\0i\0"[..]).unwrap();
+        assert_eq!((utf16.encoding.as_str(), utf16.words), ("utf-16le", None));
+    }
+
+    #[test]
+    fn renders_totals_in_every_format() {
+        let rows = vec![
+            count("a, b.txt", "one two\n".as_bytes()).unwrap(),
+            count("c.bin", &[0u8, 1][..]).unwrap(),
+        ];
+        let total = total(&rows);
+        assert_eq!((total.bytes, total.words), (10, Some(2)));
+
+        let csv = render(&rows, Some(&total), Format::Csv);
+        assert_eq!(
+            csv.lines().collect::<Vec<_>>(),
+            [
+                "name,lines,words,chars,bytes,max_line_length,encoding",
+                "\"a, b.txt\",1,2,8,8,7,ascii",
+                "c.bin,,,,2,,binary",
+                "total,1,2,8,10,7,-",
+            ]
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&render(&rows, Some(&total), Format::Json)).unwrap();
+        assert_eq!(json["total"]["bytes"], 10);
+        assert!(json["files"][1]["lines"].is_null());
+        let table = render(&rows, None, Format::Table);
+        assert!(table.lines().nth(2).unwrap().ends_with("binary    c.bin"));
+    }
+
+    #[test]
+    fn existing_files_are_not_globbed() {
+        let dir = std::env::temp_dir().join(format!("mycli-stats-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let odd = dir.join("notes[1]?.txt");
+        fs::write(&odd, "x\n").unwrap();
+        fs::write(dir.join("a.txt"), "y\n").unwrap();
+
+        let named = expand(&[odd.display().to_string()]);
+        let globbed = expand(&[format!("{}/*.txt", dir.display())]);
+        let missing = expand(&[format!("{}/nope[1].txt", dir.display())]);
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(named.unwrap(), [odd]);
+        assert_eq!(globbed.unwrap().len(), 2);
+        assert!(missing.is_err());
+    }
+}
@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde::Serialize;
+use serde_json::json;
+use tokio::net::TcpListener;
+use tokio::sync::{Notify, watch};
+
+use crate::{AppState, fixtures, guard, json_response, text_response};
+
+// Open data-plane connections, for `/connections` and for draining
+#[derive(Default)]
+pub struct ConnRegistry {
+    next_id: AtomicU64,
+    conns: Mutex<HashMap<u64, ConnInfo>>,
+    idle: Notify,
+}
+
+struct ConnInfo {
+    peer: String,
+    listener: String,
+    since: Instant,
+}
+
+#[derive(Serialize)]
+struct ConnView<'a> {
+    id: u64,
+    peer: &'a str,
+    listener: &'a str,
+    age_ms: u128,
+}
+
+// Removes its connection from the registry when the connection task ends.
+// Owns a handle on the registry so it can move into that task.
+pub struct ConnGuard {
+    registry: Arc<ConnRegistry>,
+    id: u64,
+}
+
+impl ConnRegistry {
+    // Called by the accept loop before the connection task is spawned, so a
+    // drain never sees an accepted connection missing from the registry
+    pub fn register(self: &Arc<Self>, peer: String, listener: String) -> ConnGuard {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let info = ConnInfo {
+            peer,
+            listener,
+            since: Instant::now(),
+        };
+        self.conns.lock().unwrap().insert(id, info);
+        ConnGuard {
+            registry: self.clone(),
+            id,
+        }
+    }
+
+    // Resolves once no connection is open (immediately if none is)
+    pub async fn wait_idle(&self) {
+        loop {
+            let notified = self.idle.notified();
+            if self.conns.lock().unwrap().is_empty() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    fn snapshot(&self) -> serde_json::Value {
+        let conns = self.conns.lock().unwrap();
+        let mut list: Vec<_> = conns
+            .iter()
+            .map(|(id, c)| ConnView {
+                id: *id,
+                peer: &c.peer,
+                listener: &c.listener,
+                age_ms: c.since.elapsed().as_millis(),
+            })
+            .collect();
+        list.sort_by_key(|c| c.id);
+        json!(list)
+    }
+}
+
+impl Drop for ConnGuard {
+    fn drop(&mut self) {
+        let mut conns = self.registry.conns.lock().unwrap();
+        conns.remove(&self.id);
+        if conns.is_empty() {
+            self.registry.idle.notify_waiters();
+        }
+    }
+}
+
+// Resolves once a drain has been requested
+pub async fn drained(rx: &mut watch::Receiver<bool>) {
+    let _ = rx.wait_for(|draining| *draining).await;
+}
+
+// The admin API must never be reachable from off the box
+pub async fn bind(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    if !addr.ip().is_loopback() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("admin listener must be on a loopback address, got {addr}"),
+        ));
+    }
+    TcpListener::bind(addr).await
+}
+
+// Admin accept loop; keeps running during a drain so progress stays visible
+pub async fn serve(listener: TcpListener, state: Arc<AppState>) -> std::io::Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let io = TokioIo::new(stream);
+        let state = state.clone();
+        tokio::spawn(async move {
+            let svc = service_fn(move |req| handle(req, state.clone()));
+            if let Err(err) = http1::Builder::new().serve_connection(io, svc).await {
+                log::warn!("[admin] connection error: {err}");
+            }
+        });
+    }
+}
+
+// Admin routes:
+//   GET  /config       effective configuration
+//   GET  /connections  open connections with peer and age
+//   GET  /log-level    current level
+//   PUT  /log-level    body: off|error|warn|info|debug|trace
+//   POST /drain        stop accepting, finish in-flight requests, exit
+async fn handle(
+    req: Request<Incoming>,
+    state: Arc<AppState>,
+) -> Result<Response<Full<Bytes>>, hyper::Error> {
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, "/config") => Ok(json_response(&config(&state), StatusCode::OK)),
+
+        (&Method::GET, "/connections") => {
+            Ok(json_response(&state.conns.snapshot(), StatusCode::OK))
+        }
+
+        (&Method::GET, "/log-level") => Ok(json_response(
+            &json!({ "level": log::max_level().to_string().to_lowercase() }),
+            StatusCode::OK,
+        )),
+
+        (&Method::PUT, "/log-level") => {
+            let Ok(body) = Limited::new(req.into_body(), 64).collect().await else {
+                return Ok(text_response("Bad body", StatusCode::BAD_REQUEST));
+            };
+            let body = body.to_bytes();
+            let level = std::str::from_utf8(&body)
+                .ok()
+                .and_then(|s| log::LevelFilter::from_str(s.trim()).ok());
+            match level {
+                Some(level) => {
+                    log::set_max_level(level);
+                    log::info!("[admin] log level set to {level}");
+                    Ok(text_response("ok", StatusCode::OK))
+                }
+                None => Ok(text_response(
+                    "Expected one of off|error|warn|info|debug|trace",
+                    StatusCode::BAD_REQUEST,
+                )),
+            }
+        }
+
+        (&Method::POST, "/drain") => {
+            state.drain.send_replace(true);
+            log::info!("[admin] drain requested");
+            Ok(text_response("draining", StatusCode::ACCEPTED))
+        }
+
+        _ => Ok(text_response("Not Found", StatusCode::NOT_FOUND)),
+    }
+}
+
+fn config(state: &AppState) -> serde_json::Value {
+    let fixtures = match &state.fixtures.mode {
+        fixtures::Mode::Live => json!({ "mode": "live" }),
+        fixtures::Mode::Record(dir) => json!({ "mode": "record", "dir": dir }),
+        fixtures::Mode::Replay(dir) => json!({ "mode": "replay", "dir": dir }),
+    };
+    let match_headers: Vec<_> = state
+        .fixtures
+        .match_headers
+        .iter()
+        .map(|h| h.as_str())
+        .collect();
+    json!({
+        "listeners": state.listeners,
+        "draining": *state.drain.borrow(),
+        "log_level": log::max_level().to_string().to_lowercase(),
+        "allowed_hosts": guard::ALLOWED_HOSTS,
+        "max_redirect_hops": guard::MAX_REDIRECT_HOPS,
+        "fixtures": fixtures,
+        "match_headers": match_headers,
+        "routes": {
+            "/proxy/todo": state.todo_rules,
+            "/proxy": state.proxy_rules,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{Fixtures, Mode};
+    use crate::rules::RouteRules;
+    use reqwest::Client;
+
+    fn state() -> Arc<AppState> {
+        Arc::new(AppState {
+            client: Client::new(),
+            passthrough_client: Client::new(),
+            fixtures: Fixtures {
+                mode: Mode::Live,
+                match_headers: Vec::new(),
+            },
+            todo_rules: RouteRules::proxy_default(),
+            proxy_rules: RouteRules::proxy_default(),
+            listeners: vec!["127.0.0.1:8080".into()],
+            conns: Arc::default(),
+            drain: watch::Sender::new(false),
+        })
+    }
+
+    // Serve the admin API on an ephemeral port; returns its base URL
+    async fn start(state: Arc<AppState>) -> (Client, String) {
+        let listener = bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(serve(listener, state));
+        (Client::builder().no_proxy().build().unwrap(), base)
+    }
+
+    #[tokio::test]
+    async fn refuses_non_loopback_addresses() {
+        let err = bind("0.0.0.0:0".parse().unwrap()).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(bind("127.0.0.1:0".parse().unwrap()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn reports_config_and_connections() {
+        let state = state();
+        let (client, base) = start(state.clone()).await;
+        let _conn = state
+            .conns
+            .register("10.0.0.7:5000".into(), "127.0.0.1:8080".into());
+
+        let config: serde_json::Value = client
+            .get(format!("{base}/config"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(config["listeners"], json!(["127.0.0.1:8080"]));
+        assert_eq!(config["draining"], false);
+        assert_eq!(config["fixtures"]["mode"], "live");
+        assert_eq!(
+            config["routes"]["/proxy"]["request"][1],
+            "remove authorization"
+        );
+
+        let conns: serde_json::Value = client
+            .get(format!("{base}/connections"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(conns.as_array().unwrap().len(), 1);
+        assert_eq!(conns[0]["peer"], "10.0.0.7:5000");
+        assert_eq!(conns[0]["listener"], "127.0.0.1:8080");
+
+        let resp = client.get(format!("{base}/nope")).send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    // The only test touching the global log level
+    #[tokio::test]
+    async fn log_level_can_be_changed() {
+        let (client, base) = start(state()).await;
+        let level = || async {
+            let v: serde_json::Value = client
+                .get(format!("{base}/log-level"))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            v["level"].as_str().unwrap().to_owned()
+        };
+        let put = |body: &'static str| client.put(format!("{base}/log-level")).body(body).send();
+
+        assert_eq!(put("debug\n").await.unwrap().status(), StatusCode::OK);
+        assert_eq!(level().await, "debug");
+        assert_eq!(put("loud").await.unwrap().status(), StatusCode::BAD_REQUEST);
+        assert_eq!(level().await, "debug");
+        assert_eq!(put("warn").await.unwrap().status(), StatusCode::OK);
+        assert_eq!(level().await, "warn");
+    }
+
+    #[tokio::test]
+    async fn drain_waits_for_open_connections() {
+        let state = state();
+        let (client, base) = start(state.clone()).await;
+        let conn = state
+            .conns
+            .register("10.0.0.7:5000".into(), "127.0.0.1:8080".into());
+
+        let mut rx = state.drain.subscribe();
+        let resp = client.post(format!("{base}/drain")).send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+        drained(&mut rx).await;
+
+        let conns = state.conns.clone();
+        let idle = tokio::spawn(async move { conns.wait_idle().await });
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        assert!(!idle.is_finished(), "drain finished with a connection open");
+        drop(conn);
+        idle.await.unwrap();
+    }
+}
@@ -0,0 +1,214 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bytes::Bytes;
+use hyper::StatusCode;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+
+// Status for "replay mode, but no fixture matches": outside the range any
+// real upstream uses, so it can't be mistaken for a recorded response
+pub const REPLAY_MISS: u16 = 599;
+
+// One upstream answer, whether it came off the network or off disk
+pub struct Exchange {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+// How upstream traffic is taped (or not)
+#[derive(Clone, Debug)]
+pub enum Mode {
+    Live,
+    Record(PathBuf),
+    Replay(PathBuf),
+}
+
+// Record/replay settings: where fixtures live and what makes two requests
+// "the same" (method + URL always, plus these request headers)
+#[derive(Clone, Debug)]
+pub struct Fixtures {
+    pub mode: Mode,
+    pub match_headers: Vec<HeaderName>,
+}
+
+// `<key>.json` holds everything but the body, which sits next to it in
+// `<key>.body` as raw bytes
+#[derive(Serialize, Deserialize)]
+struct Fixture {
+    request: RecordedRequest,
+    status: u16,
+    headers: Vec<(String, String)>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RecordedRequest {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Fixtures {
+    // Stable across runs and toolchains (FNV-1a), unlike std's DefaultHasher
+    pub fn key(&self, method: &str, url: &str, headers: &HeaderMap) -> String {
+        let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut feed = |bytes: &[u8]| {
+            for b in bytes.iter().chain(b"\0") {
+                h ^= u64::from(*b);
+                h = h.wrapping_mul(0x0100_0000_01b3);
+            }
+        };
+        feed(method.as_bytes());
+        feed(url.as_bytes());
+        for name in &self.match_headers {
+            feed(name.as_str().as_bytes());
+            for v in headers.get_all(name) {
+                feed(v.as_bytes());
+            }
+        }
+        format!("{h:016x}")
+    }
+
+    pub async fn save(
+        &self,
+        key: &str,
+        method: &str,
+        url: &str,
+        req_headers: &HeaderMap,
+        ex: &Exchange,
+    ) -> io::Result<()> {
+        let Mode::Record(dir) = &self.mode else {
+            return Ok(());
+        };
+        tokio::fs::create_dir_all(dir).await?;
+        let fixture = Fixture {
+            request: RecordedRequest {
+                method: method.to_string(),
+                url: url.to_string(),
+                headers: to_pairs(req_headers),
+            },
+            status: ex.status.as_u16(),
+            headers: to_pairs(&ex.headers),
+        };
+        let json = serde_json::to_vec_pretty(&fixture).map_err(io::Error::other)?;
+        // `.json` goes last: once it exists, its body is complete too
+        write_atomic(&dir.join(format!("{key}.body")), &ex.body).await?;
+        write_atomic(&dir.join(format!("{key}.json")), &json).await
+    }
+
+    // Ok(None) is a replay miss; Err is a fixture that exists but is broken
+    pub async fn load(&self, key: &str) -> io::Result<Option<Exchange>> {
+        let Mode::Replay(dir) = &self.mode else {
+            return Ok(None);
+        };
+        let json = match tokio::fs::read(dir.join(format!("{key}.json"))).await {
+            Ok(j) => j,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let fixture: Fixture = serde_json::from_slice(&json).map_err(io::Error::other)?;
+        let body = tokio::fs::read(dir.join(format!("{key}.body"))).await?;
+
+        let mut headers = HeaderMap::new();
+        for (k, v) in fixture.headers {
+            let name = HeaderName::try_from(k).map_err(io::Error::other)?;
+            let value = HeaderValue::try_from(v).map_err(io::Error::other)?;
+            headers.append(name, value);
+        }
+        Ok(Some(Exchange {
+            status: StatusCode::from_u16(fixture.status).map_err(io::Error::other)?,
+            headers,
+            body: Bytes::from(body),
+        }))
+    }
+}
+
+// Write a uniquely named temp file next to `path` and rename it over
+// `path`, so readers see the old file or the new one, never half of one
+async fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = path.with_file_name(format!(
+        ".{name}.{}.{}.tmp",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let res = match tokio::fs::write(&tmp, data).await {
+        Ok(()) => tokio::fs::rename(&tmp, path).await,
+        Err(e) => Err(e),
+    };
+    if res.is_err() {
+        let _ = tokio::fs::remove_file(&tmp).await;
+    }
+    res
+}
+
+fn to_pairs(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(k, v)| {
+            (
+                k.as_str().to_string(),
+                String::from_utf8_lossy(v.as_bytes()).into_owned(),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn recorded_exchange_replays() {
+        let dir = std::env::temp_dir().join(format!("lesson08-fixtures-{}", std::process::id()));
+        let accept = HeaderName::from_static("accept");
+        let mut req = HeaderMap::new();
+        req.insert(&accept, HeaderValue::from_static("application/json"));
+
+        let rec = Fixtures {
+            mode: Mode::Record(dir.clone()),
+            match_headers: vec![accept.clone()],
+        };
+        let url = "https://api.github.com/zen";
+        let key = rec.key("GET", url, &req);
+        let mut headers = HeaderMap::new();
+        headers.insert("x-test", HeaderValue::from_static("1"));
+        let ex = Exchange {
+            status: StatusCode::IM_A_TEAPOT,
+            headers,
+            body: Bytes::from_static(b"hi"),
+        };
+        rec.save(&key, "GET", url, &req, &ex).await.unwrap();
+        // Recording again replaces the pair and leaves no temp files behind
+        rec.save(&key, "GET", url, &req, &ex).await.unwrap();
+        let mut files: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        assert_eq!(files, [format!("{key}.body"), format!("{key}.json")]);
+
+        let play = Fixtures {
+            mode: Mode::Replay(dir.clone()),
+            ..rec
+        };
+        let got = play.load(&key).await.unwrap().unwrap();
+        assert_eq!(got.status, StatusCode::IM_A_TEAPOT);
+        assert_eq!(got.headers["x-test"], "1");
+        assert_eq!(&got.body[..], b"hi");
+
+        // A different matched header is a different fixture
+        req.insert(&accept, HeaderValue::from_static("text/html"));
+        assert!(
+            play.load(&play.key("GET", url, &req))
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
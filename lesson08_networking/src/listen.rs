@@ -0,0 +1,392 @@
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use tokio::net::{TcpListener, TcpStream};
+
+#[cfg(unix)]
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+#[cfg(unix)]
+use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+// First fd passed by a socket-activating supervisor (sd_listen_fds(3))
+#[cfg(unix)]
+const LISTEN_FDS_START: RawFd = 3;
+// More than any supervisor would pass; guards against a garbage LISTEN_FDS
+#[cfg(unix)]
+const MAX_LISTEN_FDS: RawFd = 1024;
+
+// One `--listen` entry as written on the command line
+#[derive(Clone, Debug)]
+pub enum ListenSpec {
+    // `127.0.0.1:8080` or `[::1]:8080`
+    Tcp(SocketAddr),
+    // `unix:/run/app.sock[,mode=660][,owner=UID[:GID]]`
+    #[cfg(unix)]
+    Unix(UnixSpec),
+    // `systemd`: take every fd handed over via LISTEN_FDS
+    #[cfg(unix)]
+    Inherited,
+}
+
+#[cfg(unix)]
+#[derive(Clone, Debug)]
+pub struct UnixSpec {
+    pub path: PathBuf,
+    pub mode: Option<u32>,
+    pub owner: Option<(Option<u32>, Option<u32>)>,
+}
+
+impl FromStr for ListenSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        #[cfg(unix)]
+        {
+            if s == "systemd" {
+                return Ok(ListenSpec::Inherited);
+            }
+            if let Some(rest) = s.strip_prefix("unix:") {
+                return parse_unix(rest).map(ListenSpec::Unix);
+            }
+        }
+        s.parse::<SocketAddr>()
+            .map(ListenSpec::Tcp)
+            .map_err(|e| format!("invalid listen address `{s}`: {e}"))
+    }
+}
+
+// `PATH[,mode=OCTAL][,owner=UID[:GID]]`
+#[cfg(unix)]
+fn parse_unix(s: &str) -> Result<UnixSpec, String> {
+    let mut parts = s.split(',');
+    let path = parts.next().unwrap_or_default();
+    if path.is_empty() {
+        return Err("unix listener needs a socket path".into());
+    }
+    let mut spec = UnixSpec {
+        path: PathBuf::from(path),
+        mode: None,
+        owner: None,
+    };
+    for opt in parts {
+        match opt.split_once('=') {
+            Some(("mode", v)) => {
+                let mode = u32::from_str_radix(v, 8).map_err(|_| format!("bad mode `{v}`"))?;
+                spec.mode = Some(mode);
+            }
+            Some(("owner", v)) => {
+                let (uid, gid) = v.split_once(':').unwrap_or((v, ""));
+                let id = |x: &str| -> Result<Option<u32>, String> {
+                    if x.is_empty() {
+                        Ok(None)
+                    } else {
+                        x.parse().map(Some).map_err(|_| format!("bad owner `{v}`"))
+                    }
+                };
+                spec.owner = Some((id(uid)?, id(gid)?));
+            }
+            _ => return Err(format!("unknown unix listener option `{opt}`")),
+        }
+    }
+    Ok(spec)
+}
+
+// A bound listener; accepted connections are served by the same router
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, Option<PathBuf>),
+}
+
+// Accepted connection plus a printable peer address
+pub enum Conn {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Listener {
+    pub async fn accept(&self) -> io::Result<(Conn, String)> {
+        match self {
+            Listener::Tcp(l) => {
+                let (s, peer) = l.accept().await?;
+                Ok((Conn::Tcp(s), peer.to_string()))
+            }
+            #[cfg(unix)]
+            Listener::Unix(l, _) => {
+                let (s, peer) = l.accept().await?;
+                let peer = match peer.as_pathname() {
+                    Some(p) => format!("unix:{}", p.display()),
+                    None => "unix:(unnamed)".to_string(),
+                };
+                Ok((Conn::Unix(s), peer))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Listener::Tcp(l) => match l.local_addr() {
+                Ok(addr) => write!(f, "http://{addr}"),
+                Err(_) => f.write_str("tcp:(unknown)"),
+            },
+            #[cfg(unix)]
+            Listener::Unix(_, Some(path)) => write!(f, "unix:{}", path.display()),
+            #[cfg(unix)]
+            Listener::Unix(l, None) => match l
+                .local_addr()
+                .ok()
+                .and_then(|a| a.as_pathname().map(|p| p.display().to_string()))
+            {
+                Some(p) => write!(f, "unix:{p} (inherited)"),
+                None => f.write_str("unix:(inherited)"),
+            },
+        }
+    }
+}
+
+// Remove the socket file we created once the listener goes away
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix(_, Some(path)) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+// Bind every spec in order; fails fast on the first one that cannot be bound
+pub async fn bind_all(specs: &[ListenSpec]) -> io::Result<Vec<Listener>> {
+    let mut out = Vec::new();
+    for spec in specs {
+        match spec {
+            ListenSpec::Tcp(addr) => out.push(Listener::Tcp(TcpListener::bind(addr).await?)),
+            #[cfg(unix)]
+            ListenSpec::Unix(u) => out.push(bind_unix(u)?),
+            #[cfg(unix)]
+            ListenSpec::Inherited => out.extend(inherited()?),
+        }
+    }
+    Ok(out)
+}
+
+// The socket is created inside a private 0700 directory next to its final
+// path, gets its mode and owner there, and is only then renamed into place,
+// so nobody can connect while it still has the umask's permissions
+#[cfg(unix)]
+fn bind_unix(spec: &UnixSpec) -> io::Result<Listener> {
+    use std::os::unix::fs::DirBuilderExt;
+
+    remove_stale_socket(&spec.path)?;
+    let name = spec.path.file_name().unwrap_or_default().to_string_lossy();
+    let staging = spec
+        .path
+        .with_file_name(format!(".{name}.{}.bind", std::process::id()));
+    std::fs::DirBuilder::new().mode(0o700).create(&staging)?;
+    let staged = staging.join("sock");
+
+    let bound = (|| -> io::Result<UnixListener> {
+        let listener = UnixListener::bind(&staged)?;
+        if let Some(mode) = spec.mode {
+            std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(mode))?;
+        }
+        if let Some((uid, gid)) = spec.owner {
+            std::os::unix::fs::chown(&staged, uid, gid)?;
+        }
+        std::fs::rename(&staged, &spec.path)?;
+        Ok(listener)
+    })();
+    // Only left behind when something above failed
+    let _ = std::fs::remove_file(&staged);
+    let _ = std::fs::remove_dir(&staging);
+
+    // From here on Drop takes care of unlinking the path
+    Ok(Listener::Unix(bound?, Some(spec.path.clone())))
+}
+
+// A socket file left behind by a crashed process blocks bind(); remove it,
+// but only if nobody is accepting on it anymore
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    let meta = match std::fs::symlink_metadata(path) {
+        Ok(m) => m,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if !meta.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        ));
+    }
+    match std::os::unix::net::UnixStream::connect(path) {
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("{} is in use by another process", path.display()),
+        )),
+        Err(_) => {
+            log::info!("[listen] removing stale socket {}", path.display());
+            std::fs::remove_file(path)
+        }
+    }
+}
+
+// Socket activation: LISTEN_PID must name us, LISTEN_FDS counts fds from 3
+#[cfg(unix)]
+fn inherited() -> io::Result<Vec<Listener>> {
+    let count: RawFd = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(0);
+    if !activated_for_us() || count <= 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no sockets passed via LISTEN_FDS",
+        ));
+    }
+
+    fd_range(count)?.map(listener_from_fd).collect()
+}
+
+#[cfg(unix)]
+fn fd_range(count: RawFd) -> io::Result<std::ops::Range<RawFd>> {
+    match LISTEN_FDS_START.checked_add(count) {
+        Some(end) if count <= MAX_LISTEN_FDS => Ok(LISTEN_FDS_START..end),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("LISTEN_FDS={count} is more than the {MAX_LISTEN_FDS} sockets allowed"),
+        )),
+    }
+}
+
+// Only TCP sockets yield an address through `TcpListener::local_addr`;
+// anything else is treated as a Unix socket
+#[cfg(unix)]
+fn listener_from_fd(fd: RawFd) -> io::Result<Listener> {
+    // SAFETY: the supervisor hands these fds to us and nothing else owns them
+    let tcp = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+    if tcp.local_addr().is_ok() {
+        tcp.set_nonblocking(true)?;
+        return Ok(Listener::Tcp(TcpListener::from_std(tcp)?));
+    }
+    // SAFETY: ownership moves straight from the TcpListener we just built
+    let unix = unsafe { std::os::unix::net::UnixListener::from_raw_fd(tcp.into_raw_fd()) };
+    unix.set_nonblocking(true)?;
+    // Not our file to unlink on shutdown, so no path is kept
+    Ok(Listener::Unix(UnixListener::from_std(unix)?, None))
+}
+
+// LISTEN_FDS present for this process? Then default to socket activation
+pub fn default_specs() -> Vec<ListenSpec> {
+    #[cfg(unix)]
+    if activated_for_us() {
+        return vec![ListenSpec::Inherited];
+    }
+    vec![ListenSpec::Tcp(SocketAddr::from(([127, 0, 0, 1], 8080)))]
+}
+
+#[cfg(unix)]
+fn activated_for_us() -> bool {
+    std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|p| p.parse::<u32>().ok())
+        == Some(std::process::id())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_tcp_and_rejects_garbage() {
+        let spec: ListenSpec = "[::1]:9000".parse().unwrap();
+        assert!(matches!(spec, ListenSpec::Tcp(a) if a.port() == 9000 && a.is_ipv6()));
+        let err = "localhost".parse::<ListenSpec>().unwrap_err();
+        assert!(
+            err.starts_with("invalid listen address `localhost`"),
+            "{err}"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn parses_unix_options() {
+        assert!(matches!("systemd".parse(), Ok(ListenSpec::Inherited)));
+
+        let Ok(ListenSpec::Unix(u)) = "unix:/run/app.sock,mode=660,owner=1000:50".parse() else {
+            panic!("not a unix spec");
+        };
+        assert_eq!(u.path, PathBuf::from("/run/app.sock"));
+        assert_eq!(u.mode, Some(0o660));
+        assert_eq!(u.owner, Some((Some(1000), Some(50))));
+
+        // Either half of the owner may be left out
+        let u = parse_unix("/s,owner=:50").unwrap();
+        assert_eq!((u.mode, u.owner), (None, Some((None, Some(50)))));
+        assert_eq!(
+            parse_unix("/s,owner=7").unwrap().owner,
+            Some((Some(7), None))
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn bounds_the_inherited_fd_range() {
+        assert_eq!(fd_range(2).unwrap(), 3..5);
+        assert_eq!(fd_range(MAX_LISTEN_FDS).unwrap().len(), 1024);
+        for count in [MAX_LISTEN_FDS + 1, RawFd::MAX] {
+            let err = fd_range(count).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_malformed_unix_specs() {
+        for (spec, want) in [
+            ("", "unix listener needs a socket path"),
+            (",mode=600", "unix listener needs a socket path"),
+            ("/s,mode=9", "bad mode `9`"),
+            ("/s,mode=rw", "bad mode `rw`"),
+            ("/s,owner=root", "bad owner `root`"),
+            ("/s,owner=1:x", "bad owner `1:x`"),
+            ("/s,group=1", "unknown unix listener option `group=1`"),
+            ("/s,mode", "unknown unix listener option `mode`"),
+        ] {
+            assert_eq!(parse_unix(spec).unwrap_err(), want, "{spec}");
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn binds_unix_socket_with_mode_and_cleans_up() {
+        let dir = std::env::temp_dir().join(format!("lesson08-listen-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.sock");
+        let spec = UnixSpec {
+            path: path.clone(),
+            mode: Some(0o600),
+            owner: None,
+        };
+
+        let listener = bind_unix(&spec).unwrap();
+        let meta = std::fs::symlink_metadata(&path).unwrap();
+        assert!(meta.file_type().is_socket());
+        assert_eq!(meta.permissions().mode() & 0o777, 0o600);
+        // Only the socket is left: the staging directory is gone
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        UnixStream::connect(&path).await.unwrap();
+
+        drop(listener);
+        assert!(!path.exists());
+        std::fs::remove_dir(&dir).unwrap();
+    }
+}
@@ -1,5 +1,16 @@
+mod admin;
+mod fixtures;
+mod guard;
+mod listen;
+mod rules;
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
 use bytes::Bytes;
-use http_body_util::Full;
+use clap::Parser;
+use http_body_util::{BodyExt, Full, Limited};
 use hyper::body::Incoming;
 use hyper::server::conn::http1;
 use hyper::service::service_fn;
@@ -7,7 +18,69 @@ use hyper::{Method, Request, Response, StatusCode, header};
 use hyper_util::rt::TokioIo;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use tokio::net::TcpListener;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+
+use admin::{ConnGuard, ConnRegistry};
+use fixtures::{Exchange, Fixtures};
+use listen::{Conn, ListenSpec, Listener};
+use rules::{LimitExceeded, RedirectMode, RouteRules, RulesFile};
+
+#[derive(Parser)]
+#[command(
+    name = "lesson08_networking",
+    about = "Hyper JSON server + reqwest proxy"
+)]
+struct Args {
+    // Where to listen (repeatable): HOST:PORT, [V6]:PORT,
+    // unix:PATH[,mode=660][,owner=UID[:GID]] or `systemd` for LISTEN_FDS.
+    // Default: inherited sockets if present, else 127.0.0.1:8080
+    #[arg(short, long, value_name = "ADDR")]
+    listen: Vec<ListenSpec>,
+
+    // Write every upstream exchange to fixture files in DIR
+    #[arg(long, value_name = "DIR", conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    // Answer proxy requests from fixtures in DIR; never touch the network
+    #[arg(long, value_name = "DIR")]
+    replay: Option<PathBuf>,
+
+    // Request header that must also match for a fixture to be used
+    // (repeatable; method and URL always match)
+    #[arg(long = "match-header", value_name = "NAME")]
+    match_headers: Vec<header::HeaderName>,
+
+    // JSON file overriding the header rules, redirect handling and size
+    // limits of the `todo` and `proxy` routes (see rules::RulesFile)
+    #[arg(long, value_name = "FILE")]
+    rules: Option<PathBuf>,
+
+    // Admin API (config, connections, log level, drain); loopback only
+    #[arg(long, value_name = "ADDR")]
+    admin: Option<SocketAddr>,
+
+    // Initial log level; can be changed later through the admin API
+    #[arg(long, value_name = "LEVEL", default_value = "info")]
+    log_level: log::LevelFilter,
+}
+
+// Shared by every connection on every listener
+struct AppState {
+    // Follows redirects, re-checking every hop against the guard
+    client: Client,
+    // Never follows; used by routes that hand 3xx back to the caller
+    passthrough_client: Client,
+    fixtures: Fixtures,
+    todo_rules: RouteRules,
+    proxy_rules: RouteRules,
+    // What each listener reported at startup
+    listeners: Vec<String>,
+    conns: Arc<ConnRegistry>,
+    // Flipped to true once by the admin `/drain` endpoint
+    drain: watch::Sender<bool>,
+}
 
 #[derive(Deserialize)]
 struct Todo {
@@ -58,43 +131,46 @@ fn is_hop_by_hop(name: &hyper::header::HeaderName) -> bool {
     )
 }
 
-// Minimal query parser for `?key=value`; returns value as-is (not URL-decoded)
+// First `key=value` in the query, percent-decoded
 fn extract_query_param(uri: &hyper::Uri, key: &str) -> Option<String> {
-    uri.query().and_then(|q| {
-        for pair in q.split('&') {
-            if let Some((k, v)) = pair.split_once('=') {
-                if k == key {
-                    return Some(v.to_string());
-                }
-            }
-        }
-        None
-    })
+    let query = uri.query()?;
+    url::form_urlencoded::parse(query.as_bytes())
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v.into_owned())
 }
 
-// Simple GET proxy: fetch `target` via reqwest and mirror status/body/headers
-async fn proxy_get(
-    client: &reqwest::Client,
-    target: &str,
-) -> Result<Response<Full<Bytes>>, hyper::Error> {
-    // Basic SSRF guard
-    let Ok(url) = reqwest::Url::parse(target) else {
-        return Ok(text_response("Invalid url", StatusCode::BAD_REQUEST));
-    };
-    if url.scheme() != "http" && url.scheme() != "https" {
-        return Ok(text_response("Unsupported scheme", StatusCode::BAD_REQUEST));
-    }
-    let allowed = ["jsonplaceholder.typicode.com", "api.github.com"];
-    if !allowed.iter().any(|h| Some(*h) == url.host_str()) {
-        return Ok(text_response("Host not allowed", StatusCode::FORBIDDEN));
-    }
+// Response for a request or upstream that went over one of the route limits
+fn limit_response(e: LimitExceeded) -> Response<Full<Bytes>> {
+    text_response(&e.to_string(), e.status())
+}
+
+// Live upstream fetch; the body is read under the route's size cap
+async fn fetch_upstream(
+    state: &AppState,
+    rules: &RouteRules,
+    url: &reqwest::Url,
+    fwd: header::HeaderMap,
+) -> Result<Exchange, Response<Full<Bytes>>> {
+    let limits = rules.limits;
 
     // Upstream request
-    let res = match client.get(url).send().await {
+    let client = match rules.redirects {
+        RedirectMode::Follow => &state.client,
+        RedirectMode::PassThrough => &state.passthrough_client,
+    };
+    let mut res = match client.get(url.clone()).headers(fwd).send().await {
         Ok(r) => r,
+        Err(e) if e.is_redirect() => {
+            log::warn!("[proxy] redirect refused: {e}");
+            let msg = match guard::redirect_error(&e) {
+                Some(g) => format!("Upstream redirect refused: {g}"),
+                None => "Upstream redirect refused".to_string(),
+            };
+            return Err(text_response(&msg, StatusCode::BAD_GATEWAY));
+        }
         Err(e) => {
-            eprintln!("[proxy] request error: {e}");
-            return Ok(text_response(
+            log::warn!("[proxy] request error: {e}");
+            return Err(text_response(
                 "Upstream fetch failed",
                 StatusCode::BAD_GATEWAY,
             ));
@@ -105,28 +181,172 @@ async fn proxy_get(
     let status = res.status();
     let headers_clone = res.headers().clone(); // HeaderMap implements Clone
 
-    // This consumes `res` (ok now)
-    let body_bytes = match res.bytes().await {
-        Ok(b) => b,
-        Err(e) => {
-            eprintln!("[proxy] read body error: {e}");
-            return Ok(text_response(
-                "Upstream read failed",
-                StatusCode::BAD_GATEWAY,
-            ));
+    if res
+        .content_length()
+        .is_some_and(|n| n > limits.max_response_body as u64)
+    {
+        return Err(limit_response(LimitExceeded::ResponseBody(
+            limits.max_response_body,
+        )));
+    }
+
+    // Read chunk by chunk so a lying/missing Content-Length can't blow the cap
+    let mut body_bytes = Vec::new();
+    loop {
+        match res.chunk().await {
+            Ok(Some(chunk)) => {
+                if body_bytes.len() + chunk.len() > limits.max_response_body {
+                    return Err(limit_response(LimitExceeded::ResponseBody(
+                        limits.max_response_body,
+                    )));
+                }
+                body_bytes.extend_from_slice(&chunk);
+            }
+            Ok(None) => break,
+            Err(e) => {
+                log::warn!("[proxy] read body error: {e}");
+                return Err(text_response(
+                    "Upstream read failed",
+                    StatusCode::BAD_GATEWAY,
+                ));
+            }
+        }
+    }
+
+    Ok(Exchange {
+        status,
+        headers: headers_clone,
+        body: Bytes::from(body_bytes),
+    })
+}
+
+// Replay mode had nothing for this request
+fn replay_miss(url: &reqwest::Url, key: &str) -> Response<Full<Bytes>> {
+    let status = StatusCode::from_u16(fixtures::REPLAY_MISS).unwrap_or(StatusCode::BAD_GATEWAY);
+    let mut resp = text_response(
+        &format!("Replay miss: no fixture for GET {url} (key {key})"),
+        status,
+    );
+    resp.headers_mut()
+        .insert("x-replay", header::HeaderValue::from_static("miss"));
+    resp
+}
+
+// Simple GET proxy: fetch `target` via reqwest and mirror status/body/headers,
+// with the route's header rules and size limits applied both ways
+async fn proxy_get(
+    state: &AppState,
+    req: Request<Incoming>,
+    target: &str,
+    rules: &RouteRules,
+) -> Result<Response<Full<Bytes>>, hyper::Error> {
+    let limits = rules.limits;
+    let (parts, body) = req.into_parts();
+
+    // Client-side limits first, before we talk to anybody
+    if parts.headers.len() > limits.max_request_headers {
+        return Ok(limit_response(LimitExceeded::RequestHeaders(
+            limits.max_request_headers,
+        )));
+    }
+    let declared = parts
+        .headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+    if declared.is_some_and(|n| n > limits.max_request_body) {
+        return Ok(limit_response(LimitExceeded::RequestBody(
+            limits.max_request_body,
+        )));
+    }
+    // GET bodies are not forwarded, but a chunked one still has to fit
+    if let Err(e) = Limited::new(body, limits.max_request_body).collect().await {
+        if e.is::<http_body_util::LengthLimitError>() {
+            return Ok(limit_response(LimitExceeded::RequestBody(
+                limits.max_request_body,
+            )));
+        }
+        return Ok(text_response(
+            "Request read failed",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    // Basic SSRF guard
+    let url = match guard::parse_target(target) {
+        Ok(u) => u,
+        Err(e) => return Ok(text_response(&e.to_string(), e.status())),
+    };
+
+    // Forward the client's end-to-end headers, then let the route edit them
+    let mut fwd = header::HeaderMap::new();
+    for (name, value) in parts.headers.iter() {
+        if !is_hop_by_hop(name) && name != header::HOST && name != header::CONTENT_LENGTH {
+            fwd.append(name.clone(), value.clone());
+        }
+    }
+    rules::apply(&rules.request, &mut fwd);
+
+    // Upstream exchange: from disk in replay mode, else live (and maybe taped)
+    let key = state.fixtures.key("GET", url.as_str(), &fwd);
+    let ex = if let fixtures::Mode::Replay(_) = state.fixtures.mode {
+        match state.fixtures.load(&key).await {
+            Ok(Some(ex)) => ex,
+            Ok(None) => return Ok(replay_miss(&url, &key)),
+            Err(e) => {
+                log::error!("[replay] unreadable fixture {key}: {e}");
+                return Ok(text_response(
+                    "Replay fixture unreadable",
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                ));
+            }
         }
+    } else {
+        let ex = match fetch_upstream(state, rules, &url, fwd.clone()).await {
+            Ok(ex) => ex,
+            Err(resp) => return Ok(resp),
+        };
+        if let Err(e) = state
+            .fixtures
+            .save(&key, "GET", url.as_str(), &fwd, &ex)
+            .await
+        {
+            log::error!("[record] failed to write fixture {key}: {e}");
+        }
+        ex
     };
+    let Exchange {
+        status,
+        headers: headers_clone,
+        body: body_bytes,
+    } = ex;
+
+    // Limits apply to replayed fixtures just like to live traffic
+    if headers_clone.len() > limits.max_response_headers {
+        return Ok(limit_response(LimitExceeded::ResponseHeaders(
+            limits.max_response_headers,
+        )));
+    }
+    if body_bytes.len() > limits.max_response_body {
+        return Ok(limit_response(LimitExceeded::ResponseBody(
+            limits.max_response_body,
+        )));
+    }
 
     // Build downstream response
-    let mut out = Response::new(Full::new(Bytes::from(body_bytes.to_vec())));
+    let mut out = Response::new(Full::new(body_bytes));
     *out.status_mut() = StatusCode::from_u16(status.as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
 
-    // Copy end-to-end headers only
+    // Copy end-to-end headers only (append keeps repeated ones intact)
     for (name, value) in headers_clone.iter() {
         if !is_hop_by_hop(name) {
-            out.headers_mut().insert(name.clone(), value.clone());
+            out.headers_mut().append(name.clone(), value.clone());
         }
     }
+    if status.is_redirection() {
+        rules::rewrite_location(out.headers_mut(), &url, rules.location);
+    }
+    rules::apply(&rules.response, out.headers_mut());
 
     Ok(out)
 }
@@ -134,18 +354,24 @@ async fn proxy_get(
 // Hyper handler: routes /, /proxy/todo, and /proxy?url=...
 async fn handle(
     req: Request<Incoming>,
-    client: Client,
+    state: Arc<AppState>,
 ) -> Result<Response<Full<Bytes>>, hyper::Error> {
     match (req.method(), req.uri().path()) {
         // Fixed proxy endpoint for a sample JSON
         (&Method::GET, "/proxy/todo") => {
-            proxy_get(&client, "https://jsonplaceholder.typicode.com/todos/1").await
+            proxy_get(
+                &state,
+                req,
+                "https://jsonplaceholder.typicode.com/todos/1",
+                &state.todo_rules,
+            )
+            .await
         }
 
         // Dynamic proxy endpoint: /proxy?url=https://host/path
         (&Method::GET, "/proxy") => {
             if let Some(url) = extract_query_param(req.uri(), "url") {
-                proxy_get(&client, &url).await
+                proxy_get(&state, req, &url, &state.proxy_rules).await
             } else {
                 Ok(text_response(
                     "Missing url query param",
@@ -168,34 +394,148 @@ async fn handle(
     }
 }
 
+// Serve one accepted connection (TCP or Unix) with the shared router.
+// `tracked` keeps it in the registry until it closes; it finishes
+// gracefully on drain.
+fn serve<S>(stream: S, state: Arc<AppState>, tracked: ConnGuard)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let io = TokioIo::new(stream);
+    tokio::spawn(async move {
+        let _tracked = tracked;
+        let mut drain = state.drain.subscribe();
+        let svc_state = state.clone();
+        let svc = service_fn(move |req| handle(req, svc_state.clone()));
+        let conn = http1::Builder::new().serve_connection(io, svc);
+        tokio::pin!(conn);
+        let res = tokio::select! {
+            res = conn.as_mut() => res,
+            _ = admin::drained(&mut drain) => {
+                conn.as_mut().graceful_shutdown();
+                conn.await
+            }
+        };
+        if let Err(err) = res {
+            log::warn!("server error: {err}");
+        }
+    });
+}
+
+// Accept loop for a single listener; returns once a drain starts
+async fn accept_loop(listener: Listener, state: Arc<AppState>) -> std::io::Result<()> {
+    let label = listener.to_string();
+    let mut drain = state.drain.subscribe();
+    loop {
+        let (conn, peer) = tokio::select! {
+            res = listener.accept() => res?,
+            _ = admin::drained(&mut drain) => return Ok(()),
+        };
+        log::debug!("[{label}] accepted {peer}");
+        let tracked = state.conns.register(peer, label.clone());
+        match conn {
+            Conn::Tcp(s) => serve(s, state.clone(), tracked),
+            #[cfg(unix)]
+            Conn::Unix(s) => serve(s, state.clone(), tracked),
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    // Let everything through env_logger and gate on log's global max level,
+    // which the admin API can move at runtime
+    env_logger::Builder::new()
+        .filter_level(log::LevelFilter::Trace)
+        .init();
+    log::set_max_level(args.log_level);
+
+    let fixtures = Fixtures {
+        mode: match (args.record, args.replay) {
+            (Some(dir), _) => fixtures::Mode::Record(dir),
+            (_, Some(dir)) => fixtures::Mode::Replay(dir),
+            _ => fixtures::Mode::Live,
+        },
+        match_headers: args.match_headers,
+    };
+
+    // Bad rules should fail before anything touches the network
+    let rules_file = match &args.rules {
+        Some(path) => RulesFile::load(path)?,
+        None => RulesFile::default(),
+    };
+
     // ---- Reqwest: fetch JSON and print a field (demo) ----
-    let client = Client::new();
-    let todo: Todo = client
-        .get("https://jsonplaceholder.typicode.com/todos/1")
-        .send()
-        .await?
-        .error_for_status()?
-        .json()
-        .await?;
-    println!("Title: {}", todo.title);
+    let client = Client::builder()
+        .redirect(guard::redirect_policy(guard::MAX_REDIRECT_HOPS))
+        .build()?;
+    // Replay mode is for offline runs, so skip the live demo there
+    if !matches!(fixtures.mode, fixtures::Mode::Replay(_)) {
+        let todo: Todo = client
+            .get("https://jsonplaceholder.typicode.com/todos/1")
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        println!("Title: {}", todo.title);
+    }
 
     // ---- Hyper server bootstrap ----
-    let addr = "127.0.0.1:8080";
-    let listener = TcpListener::bind(addr).await?;
-    println!("Server running on http://{addr}");
+    let specs = if args.listen.is_empty() {
+        listen::default_specs()
+    } else {
+        args.listen
+    };
+    let listeners = listen::bind_all(&specs).await?;
+    let admin_listener = match args.admin {
+        Some(addr) => Some(admin::bind(addr).await?),
+        None => None,
+    };
 
-    loop {
-        let (stream, _) = listener.accept().await?;
-        let io = TokioIo::new(stream);
-        let client_cloned = client.clone();
+    let state = Arc::new(AppState {
+        client,
+        passthrough_client: Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?,
+        fixtures,
+        todo_rules: RouteRules::proxy_default().patched(rules_file.todo),
+        // Hand redirects back (Location rewritten to come through us again)
+        proxy_rules: RouteRules {
+            redirects: RedirectMode::PassThrough,
+            ..RouteRules::proxy_default()
+        }
+        .patched(rules_file.proxy),
+        listeners: listeners.iter().map(ToString::to_string).collect(),
+        conns: Arc::default(),
+        drain: watch::Sender::new(false),
+    });
 
+    if let Some(l) = admin_listener {
+        println!("Admin API on http://{}", l.local_addr()?);
+        let state = state.clone();
         tokio::spawn(async move {
-            let svc = service_fn(move |req| handle(req, client_cloned.clone()));
-            if let Err(err) = http1::Builder::new().serve_connection(io, svc).await {
-                eprintln!("server error: {err}");
+            if let Err(e) = admin::serve(l, state).await {
+                log::error!("[admin] listener failed, admin API is gone: {e}");
             }
         });
     }
+
+    // One accept loop per listener, all sharing the same router
+    let mut loops = JoinSet::new();
+    for listener in listeners {
+        println!("Server running on {listener}");
+        loops.spawn(accept_loop(listener, state.clone()));
+    }
+
+    // Loops only return on their own during a drain; anything else is fatal
+    while let Some(res) = loops.join_next().await {
+        res??;
+    }
+    log::info!("listeners closed, waiting for open connections");
+    state.conns.wait_idle().await;
+    println!("Drained, exiting");
+    Ok(())
 }
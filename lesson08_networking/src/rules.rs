@@ -0,0 +1,400 @@
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use hyper::StatusCode;
+use hyper::header::{self, HeaderMap, HeaderName, HeaderValue};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+// One header edit, applied in order to a request or response HeaderMap
+#[derive(Clone, Debug)]
+pub enum HeaderOp {
+    // Append a value, keeping any existing ones
+    Add(HeaderName, HeaderValue),
+    // Drop every value of the header
+    Remove(HeaderName),
+    // Move every value from the first name to the second
+    Rename(HeaderName, HeaderName),
+}
+
+impl fmt::Display for HeaderOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderOp::Add(name, value) => write!(f, "add {name}: {value:?}"),
+            HeaderOp::Remove(name) => write!(f, "remove {name}"),
+            HeaderOp::Rename(from, to) => write!(f, "rename {from} -> {to}"),
+        }
+    }
+}
+
+// Parses the Display form back: `add NAME: VALUE` (the value may be quoted),
+// `remove NAME` or `rename FROM -> TO`
+impl FromStr for HeaderOp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = |n: &str| {
+            HeaderName::from_str(n.trim()).map_err(|_| format!("bad header name `{}`", n.trim()))
+        };
+        let (verb, rest) = s.trim().split_once(' ').unwrap_or((s.trim(), ""));
+        match verb {
+            "add" => {
+                let (n, v) = rest
+                    .split_once(':')
+                    .ok_or_else(|| format!("expected `add NAME: VALUE`, got `{s}`"))?;
+                let v = v.trim();
+                let v = v
+                    .strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .unwrap_or(v);
+                let value =
+                    HeaderValue::from_str(v).map_err(|_| format!("bad header value `{v}`"))?;
+                Ok(HeaderOp::Add(name(n)?, value))
+            }
+            "remove" => Ok(HeaderOp::Remove(name(rest)?)),
+            "rename" => {
+                let (from, to) = rest
+                    .split_once("->")
+                    .ok_or_else(|| format!("expected `rename FROM -> TO`, got `{s}`"))?;
+                Ok(HeaderOp::Rename(name(from)?, name(to)?))
+            }
+            _ => Err(format!("unknown header op `{s}` (add, remove or rename)")),
+        }
+    }
+}
+
+// Shown as its Display form in the admin `/config` dump
+impl Serialize for HeaderOp {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+// Written the same way in a `--rules` file
+impl<'de> Deserialize<'de> for HeaderOp {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        String::deserialize(d)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+// What to do with a `Location` header on upstream 3xx responses
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LocationRewrite {
+    // Pass it through untouched
+    Keep,
+    // Point it back at `/proxy?url=...` so the client stays behind the proxy
+    ViaProxy,
+}
+
+// Whether upstream 3xx responses are followed or returned to the client
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RedirectMode {
+    // Follow on the server side, each hop re-checked by the guard
+    Follow,
+    // Return the 3xx as-is (subject to `LocationRewrite`)
+    PassThrough,
+}
+
+// Size caps; 0 is not special, it really means "nothing allowed".
+// Caps left out of a `--rules` file keep their default.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub max_request_body: usize,
+    pub max_request_headers: usize,
+    pub max_response_body: usize,
+    pub max_response_headers: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_request_body: 64 * 1024,
+            max_request_headers: 64,
+            max_response_body: 8 * 1024 * 1024,
+            max_response_headers: 100,
+        }
+    }
+}
+
+// Everything a proxy route may do to the traffic passing through it
+#[derive(Clone, Debug, Serialize)]
+pub struct RouteRules {
+    pub request: Vec<HeaderOp>,
+    pub response: Vec<HeaderOp>,
+    pub location: LocationRewrite,
+    pub redirects: RedirectMode,
+    pub limits: Limits,
+}
+
+// Client credentials never reach upstream, whatever a `--rules` file says
+fn strip_credentials() -> Vec<HeaderOp> {
+    vec![
+        HeaderOp::Remove(header::COOKIE),
+        HeaderOp::Remove(header::AUTHORIZATION),
+    ]
+}
+
+impl RouteRules {
+    // Defaults for the proxy routes: keep client credentials away from
+    // upstream and third-party cookies/fingerprints away from the client
+    pub fn proxy_default() -> Self {
+        Self {
+            request: strip_credentials(),
+            response: vec![
+                HeaderOp::Remove(header::SET_COOKIE),
+                HeaderOp::Remove(header::SERVER),
+                HeaderOp::Remove(header::STRICT_TRANSPORT_SECURITY),
+                HeaderOp::Rename(
+                    HeaderName::from_static("x-powered-by"),
+                    HeaderName::from_static("x-upstream-powered-by"),
+                ),
+                HeaderOp::Add(header::VIA, HeaderValue::from_static("1.1 lesson08")),
+            ],
+            location: LocationRewrite::ViaProxy,
+            redirects: RedirectMode::Follow,
+            limits: Limits::default(),
+        }
+    }
+
+    // Replace whatever the patch sets; everything else stays as it was.
+    // Request ops run after the credential stripping, which can't be dropped.
+    pub fn patched(mut self, patch: Option<RoutePatch>) -> Self {
+        let Some(p) = patch else {
+            return self;
+        };
+        if let Some(ops) = p.request {
+            self.request = strip_credentials();
+            self.request.extend(ops);
+        }
+        if let Some(ops) = p.response {
+            self.response = ops;
+        }
+        if let Some(location) = p.location {
+            self.location = location;
+        }
+        if let Some(redirects) = p.redirects {
+            self.redirects = redirects;
+        }
+        if let Some(limits) = p.limits {
+            self.limits = limits;
+        }
+        self
+    }
+}
+
+// One route's overrides from a `--rules` file
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoutePatch {
+    pub request: Option<Vec<HeaderOp>>,
+    pub response: Option<Vec<HeaderOp>>,
+    pub location: Option<LocationRewrite>,
+    pub redirects: Option<RedirectMode>,
+    pub limits: Option<Limits>,
+}
+
+// `--rules FILE`: JSON with an optional object per route, e.g.
+// `{"proxy": {"response": ["remove server", "add via: 1.1 edge"],
+//   "limits": {"max_response_body": 1048576}}}`
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RulesFile {
+    // `/proxy/todo`
+    pub todo: Option<RoutePatch>,
+    // `/proxy?url=...`
+    pub proxy: Option<RoutePatch>,
+}
+
+impl RulesFile {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        serde_json::from_str(&text).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("bad rules in {}: {e}", path.display()),
+            )
+        })
+    }
+}
+
+// Apply ops in order; later ops see the result of earlier ones
+pub fn apply(ops: &[HeaderOp], headers: &mut HeaderMap) {
+    for op in ops {
+        match op {
+            HeaderOp::Add(name, value) => {
+                headers.append(name.clone(), value.clone());
+            }
+            HeaderOp::Remove(name) => {
+                headers.remove(name);
+            }
+            HeaderOp::Rename(from, to) => {
+                let values: Vec<_> = headers.get_all(from).iter().cloned().collect();
+                headers.remove(from);
+                for v in values {
+                    headers.append(to.clone(), v);
+                }
+            }
+        }
+    }
+}
+
+// Resolve a (possibly relative) Location against the upstream URL and,
+// for `ViaProxy`, wrap it as `/proxy?url=...`. The target is percent-encoded
+// so its own query (`?a=1&b=2`) survives as part of the one parameter.
+pub fn rewrite_location(headers: &mut HeaderMap, upstream: &reqwest::Url, mode: LocationRewrite) {
+    if mode == LocationRewrite::Keep {
+        return;
+    }
+    let Some(loc) = headers.get(header::LOCATION).and_then(|v| v.to_str().ok()) else {
+        return;
+    };
+    let Ok(absolute) = upstream.join(loc) else {
+        return;
+    };
+    let query: String = url::form_urlencoded::Serializer::new(String::new())
+        .append_pair("url", absolute.as_str())
+        .finish();
+    if let Ok(v) = HeaderValue::from_str(&format!("/proxy?{query}")) {
+        headers.insert(header::LOCATION, v);
+    }
+}
+
+// Which limit a request or upstream response ran into
+#[derive(Debug)]
+pub enum LimitExceeded {
+    RequestBody(usize),
+    RequestHeaders(usize),
+    ResponseBody(usize),
+    ResponseHeaders(usize),
+}
+
+impl LimitExceeded {
+    // Client-side violations are 413; upstream ones are our gateway failing
+    pub fn status(&self) -> StatusCode {
+        match self {
+            LimitExceeded::RequestBody(_) | LimitExceeded::RequestHeaders(_) => {
+                StatusCode::PAYLOAD_TOO_LARGE
+            }
+            LimitExceeded::ResponseBody(_) | LimitExceeded::ResponseHeaders(_) => {
+                StatusCode::BAD_GATEWAY
+            }
+        }
+    }
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitExceeded::RequestBody(max) => {
+                write!(f, "Request body exceeds max_request_body ({max} bytes)")
+            }
+            LimitExceeded::RequestHeaders(max) => {
+                write!(f, "Request has more than max_request_headers ({max})")
+            }
+            LimitExceeded::ResponseBody(max) => {
+                write!(f, "Upstream body exceeds max_response_body ({max} bytes)")
+            }
+            LimitExceeded::ResponseHeaders(max) => {
+                write!(f, "Upstream sent more than max_response_headers ({max})")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rename_moves_every_value() {
+        let mut h = HeaderMap::new();
+        h.append("x-a", HeaderValue::from_static("1"));
+        h.append("x-a", HeaderValue::from_static("2"));
+        let to = HeaderName::from_static("x-b");
+        apply(
+            &[HeaderOp::Rename(HeaderName::from_static("x-a"), to.clone())],
+            &mut h,
+        );
+        assert!(h.get("x-a").is_none());
+        assert_eq!(h.get_all(&to).iter().count(), 2);
+    }
+
+    #[test]
+    fn relative_location_goes_via_proxy() {
+        let mut h = HeaderMap::new();
+        h.insert(header::LOCATION, HeaderValue::from_static("/todos/2"));
+        let up = reqwest::Url::parse("https://jsonplaceholder.typicode.com/todos/1").unwrap();
+        rewrite_location(&mut h, &up, LocationRewrite::ViaProxy);
+        assert_eq!(
+            h[header::LOCATION],
+            "/proxy?url=https%3A%2F%2Fjsonplaceholder.typicode.com%2Ftodos%2F2"
+        );
+    }
+
+    #[test]
+    fn location_query_survives_the_round_trip() {
+        let mut h = HeaderMap::new();
+        h.insert(
+            header::LOCATION,
+            HeaderValue::from_static("/search?a=1&b=2&q=x+y%21"),
+        );
+        let up = reqwest::Url::parse("https://example.com/start").unwrap();
+        rewrite_location(&mut h, &up, LocationRewrite::ViaProxy);
+
+        let uri: hyper::Uri = h[header::LOCATION].to_str().unwrap().parse().unwrap();
+        assert_eq!(uri.path(), "/proxy");
+        let pairs: Vec<_> = url::form_urlencoded::parse(uri.query().unwrap().as_bytes()).collect();
+        assert_eq!(pairs.len(), 1);
+        assert_eq!(pairs[0].0, "url");
+        assert_eq!(pairs[0].1, "https://example.com/search?a=1&b=2&q=x+y%21");
+    }
+
+    #[test]
+    fn rules_file_patches_defaults() {
+        let file: RulesFile = serde_json::from_str(
+            r#"{
+                "proxy": {
+                    "request": ["add x-edge: 1"],
+                    "response": ["add via: \"1.1 edge\"", "rename server -> x-server"],
+                    "redirects": "pass_through",
+                    "limits": {"max_response_body": 1024}
+                }
+            }"#,
+        )
+        .unwrap();
+        let todo = RouteRules::proxy_default().patched(file.todo);
+        assert_eq!(todo.redirects, RedirectMode::Follow);
+        assert_eq!(todo.request.len(), 2);
+
+        let proxy = RouteRules::proxy_default().patched(file.proxy);
+        assert_eq!(proxy.redirects, RedirectMode::PassThrough);
+        assert_eq!(proxy.location, LocationRewrite::ViaProxy);
+        assert_eq!(proxy.limits.max_response_body, 1024);
+        assert_eq!(proxy.limits.max_request_headers, 64);
+        let ops: Vec<String> = proxy.response.iter().map(ToString::to_string).collect();
+        assert_eq!(ops, ["add via: \"1.1 edge\"", "rename server -> x-server"]);
+        // The custom list runs after credentials are stripped, not instead
+        let mut h = HeaderMap::new();
+        h.insert(header::AUTHORIZATION, HeaderValue::from_static("Bearer t"));
+        h.insert(header::COOKIE, HeaderValue::from_static("sid=1"));
+        apply(&proxy.request, &mut h);
+        assert!(h.get(header::AUTHORIZATION).is_none());
+        assert!(h.get(header::COOKIE).is_none());
+        assert_eq!(h["x-edge"], "1");
+
+        for bad in [
+            r#"{"proxy": {"request": ["drop cookie"]}}"#,
+            r#"{"proxy": {"response": ["add x-a"]}}"#,
+            r#"{"proxy": {"limits": {"max_body": 1}}}"#,
+            r#"{"other": {}}"#,
+        ] {
+            assert!(serde_json::from_str::<RulesFile>(bad).is_err(), "{bad}");
+        }
+    }
+}